@@ -1,12 +1,12 @@
 use ptyprocess::PtyProcess;
 use std::{
     fs::File,
-    io::{self, Read, Write},
+    io::{self, Write},
     process::Command,
 };
 
 fn main() {
-    let process = PtyProcess::spawn(Command::new("cat")).expect("Error while spawning process");
+    let mut process = PtyProcess::spawn(Command::new("cat")).expect("Error while spawning process");
     let mut stream = process
         .get_pty_stream()
         .expect("Failed to get a pty handle");
@@ -19,15 +19,5 @@ fn main() {
         .write_all(&[4])
         .expect("Error while exiting a process");
 
-    // We can't read_to_end as the process isn't DEAD but at time time it is it's already a EOF
-
-    let mut buf = [0; 128];
-    loop {
-        let n = stream.read(&mut buf).expect("Erorr on read");
-        print!("{}", String::from_utf8_lossy(&buf[..n]));
-
-        if n == 0 {
-            break;
-        }
-    }
+    process.pipe_to(&mut io::stdout()).expect("Erorr on read");
 }
@@ -35,25 +35,33 @@
 pub mod stream;
 
 pub use nix::errno;
+pub use nix::sys::resource::{rlim_t, Resource};
 pub use nix::sys::signal::Signal;
+pub use nix::sys::termios::{ControlFlags, InputFlags, LocalFlags, OutputFlags};
 pub use nix::sys::wait::WaitStatus;
 pub use nix::Error;
 
 use nix::fcntl::{fcntl, open, FcntlArg, FdFlag, OFlag};
 use nix::libc::{self, winsize, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
-use nix::pty::PtyMaster;
-use nix::pty::{grantpt, posix_openpt, unlockpt};
-use nix::sys::stat::Mode;
-use nix::sys::wait::{self, waitpid};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::resource::{getrlimit, setrlimit};
+use nix::sys::stat::{self, Mode};
+use nix::sys::wait;
 use nix::sys::{signal, termios};
 use nix::unistd::{
     self, close, dup, dup2, fork, isatty, pipe, setsid, sysconf, write, ForkResult, Pid, SysconfVar,
 };
 use nix::{ioctl_write_ptr_bad, Result};
 use signal::Signal::SIGKILL;
+use std::convert::TryFrom;
 use std::fs::File;
-use std::os::unix::prelude::{AsRawFd, CommandExt, FromRawFd, RawFd};
+use std::io::{self, Read, Write};
+use std::ops::ControlFlow;
+use std::os::unix::prelude::{AsFd, AsRawFd, BorrowedFd, CommandExt, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{self, Duration};
 use stream::Stream;
@@ -67,6 +75,20 @@ const DEFAULT_INTR_CHAR: u8 = 0x3; // ^C
 
 const DEFAULT_TERMINATE_DELAY: Duration = Duration::from_millis(100);
 
+// A response of a VT100 with advanced video option.
+const DEVICE_ATTRIBUTES_RESPONSE: &[u8] = b"\x1b[?1;2c";
+
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// An upper bound of the time a drop takes to terminate a child.
+const DROP_TIMEOUT: Duration = Duration::from_secs(2);
+
+// A part of [DROP_TIMEOUT] which is always left for SIGKILL to take effect.
+const DROP_REAP_TIMEOUT: Duration = Duration::from_secs(1);
+
+const TERMINAL_RESET_SEQUENCES: &[u8] =
+    b"\x1b[?1000l\x1b[?1002l\x1b[?1003l\x1b[?1006l\x1b[?2004l\x1b[?1049l\x1b[?25h";
+
 /// PtyProcess controls a spawned process and communication with this.
 ///
 /// It implements [std::io::Read] and [std::io::Write] to communicate with
@@ -84,10 +106,19 @@ const DEFAULT_TERMINATE_DELAY: Duration = Duration::from_millis(100);
 #[derive(Debug)]
 pub struct PtyProcess {
     master: Master,
+    // a slave is held only to be closed together with the process
+    slave: Option<OwnedFd>,
+    stderr_master: Option<Master>,
+    pidfd: Option<OwnedFd>,
     child_pid: Pid,
     eof_char: u8,
     intr_char: u8,
     terminate_delay: Duration,
+    last_status: Mutex<Option<i32>>,
+    external_status: Option<WaitStatus>,
+    last_window_size: Mutex<(u16, u16)>,
+    kill_on_drop_suppressions: Arc<AtomicUsize>,
+    drop_signal: Option<(Signal, Duration)>,
 }
 
 impl PtyProcess {
@@ -98,84 +129,137 @@ impl PtyProcess {
     ///   # use ptyprocess::PtyProcess;
     ///     let proc = PtyProcess::spawn(Command::new("bash"));
     /// ```
-    pub fn spawn(mut command: Command) -> Result<Self> {
-        let master = Master::open()?;
-        master.grant_slave_access()?;
-        master.unlock_slave()?;
-
-        // handle errors in child executions by pipe
-        let (exec_err_pipe_r, exec_err_pipe_w) = pipe()?;
-
-        let fork = unsafe { fork()? };
-        match fork {
-            ForkResult::Child => {
-                let err = || -> Result<()> {
-                    make_controlling_tty(&master)?;
-
-                    let slave_fd = master.get_slave_fd()?;
-                    redirect_std_streams(slave_fd)?;
-
-                    set_echo(STDIN_FILENO, false)?;
-                    set_term_size(STDIN_FILENO, DEFAULT_TERM_COLS, DEFAULT_TERM_ROWS)?;
-
-                    // Do not allow child to inherit open file descriptors from parent
-                    close_all_descriptors(&[
-                        0,
-                        1,
-                        2,
-                        slave_fd,
-                        exec_err_pipe_w,
-                        exec_err_pipe_r,
-                        master.as_raw_fd(),
-                    ])?;
+    pub fn spawn(command: Command) -> Result<Self> {
+        PtyProcessBuilder::new().spawn(command)
+    }
 
-                    close(slave_fd)?;
-                    close(exec_err_pipe_r)?;
-                    drop(master);
+    /// Spawns a command, passes `input` to it and reads its output until EOF.
+    ///
+    /// The input is followed by an EOF character, so the child sees the end of input.
+    /// In canonical mode an EOF character only flushes a line if it's not empty,
+    /// so it's sent twice if `input` doesn't end with a newline.
+    /// Keep in mind that a terminal limits a line length (4095 bytes on Linux),
+    /// so longer lines are truncated.
+    ///
+    /// Input is written as a child becomes ready to accept it, so a child is free to produce output meanwhile.
+    /// If the child exits before reading all of the input the rest of it is dropped.
+    ///
+    /// A [io::ErrorKind::TimedOut] error is returned if EOF isn't reached within `timeout`,
+    /// in which case the child is terminated.
+    pub fn run_with_input(
+        command: Command,
+        input: &[u8],
+        timeout: Duration,
+    ) -> io::Result<(Vec<u8>, WaitStatus)> {
+        let mut process = Self::spawn(command)?;
+
+        let deadline = time::Instant::now() + timeout;
+        let mut output = Vec::new();
+        process.exchange(&mut &input[..], &mut output, Some(deadline))?;
+
+        let status = process.wait()?;
+
+        Ok((output, status))
+    }
 
-                    // close pipe on sucessfull exec
-                    fcntl(exec_err_pipe_w, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    /// Creates a [PtyProcess] from a pty allocated elsewhere, e.g. by `openpty`,
+    /// and a process attached to it.
+    ///
+    /// The `master` and `slave` must be the two ends of the same pty,
+    /// otherwise an `EINVAL` error is returned.
+    ///
+    /// [PtyProcess] takes ownership of both descriptors and closes them on drop.
+    /// The slave is kept open so the pty isn't hung up while the child is being set up.
+    ///
+    /// The `child_pid` must be a child of a current process,
+    /// as it's reaped by [Self::wait] and [Self::status] and terminated on drop
+    /// the same way as a spawned one.
+    /// If a caller reaps it on its own [Self::mark_exited] must be called.
+    pub fn from_pair(master: OwnedFd, slave: OwnedFd, child_pid: Pid) -> Result<Self> {
+        let master = Master::from_fd(master)?;
+
+        let pair_slave = open_parent_slave(&master)?;
+        let pair_slave_rdev = stat::fstat(pair_slave.as_raw_fd())?.st_rdev;
+        drop(pair_slave);
+
+        if pair_slave_rdev != stat::fstat(slave.as_raw_fd())?.st_rdev {
+            return Err(Error::EINVAL);
+        }
 
-                    let _ = command.exec();
-                    Err(Error::last())
-                }()
-                .unwrap_err();
+        Self::new(master, Some(slave), child_pid)
+    }
 
-                let code = err as i32;
+    /// Creates a [PtyProcess] from a pty master opened elsewhere, e.g. by `forkpty`,
+    /// and a process attached to it.
+    ///
+    /// The `master` must be a pty master, otherwise an error is returned.
+    /// [PtyProcess] takes ownership of it and closes it on drop,
+    /// the descriptor is used as is without allocating another pty.
+    /// End of file and interrupt characters are taken from the master's settings.
+    ///
+    /// The same requirements as for [Self::from_pair] apply to `child_pid`.
+    pub fn from_raw_master(master: OwnedFd, child_pid: Pid) -> Result<Self> {
+        let master = Master::from_fd(master)?;
+        Self::new(master, None, child_pid)
+    }
 
-                // Intentionally ignoring errors to exit the process properly
-                let _ = write(exec_err_pipe_w, &code.to_be_bytes());
-                let _ = close(exec_err_pipe_w);
+    fn new(master: Master, slave: Option<OwnedFd>, child_pid: Pid) -> Result<Self> {
+        let window_size = get_term_size(master.as_raw_fd())?;
+        let eof_char = get_eof_char(master.as_raw_fd());
+        let intr_char = get_intr_char(master.as_raw_fd());
+
+        Ok(Self {
+            master,
+            slave,
+            stderr_master: None,
+            pidfd: open_pidfd(child_pid),
+            child_pid,
+            eof_char,
+            intr_char,
+            terminate_delay: DEFAULT_TERMINATE_DELAY,
+            last_status: Mutex::new(None),
+            external_status: None,
+            last_window_size: Mutex::new(window_size),
+            kill_on_drop_suppressions: Arc::default(),
+            drop_signal: None,
+        })
+    }
 
-                process::exit(code);
-            }
-            ForkResult::Parent { child } => {
-                close(exec_err_pipe_w)?;
-
-                let mut pipe_buf = [0u8; 4];
-                unistd::read(exec_err_pipe_r, &mut pipe_buf)?;
-                close(exec_err_pipe_r)?;
-                let code = i32::from_be_bytes(pipe_buf);
-                if code != 0 {
-                    return Err(errno::from_i32(code));
-                }
+    /// Spawns a child process and reports how long each stage of a spawn took.
+    ///
+    /// It's available with the `spawn-timings` feature.
+    #[cfg(feature = "spawn-timings")]
+    pub fn spawn_timed(command: Command) -> Result<(Self, SpawnTimings)> {
+        PtyProcessBuilder::new().spawn_timed(command)
+    }
 
-                // Some systems may work in this way? (not sure)
-                // that we need to set a terminal size in a parent.
-                set_term_size(master.as_raw_fd(), DEFAULT_TERM_COLS, DEFAULT_TERM_ROWS)?;
+    /// Spawns a child process with a terminal size of `term_fd`.
+    ///
+    /// A default size is used if `term_fd` is not a terminal.
+    ///
+    /// ```no_run
+    ///   # use std::process::Command;
+    ///   # use ptyprocess::PtyProcess;
+    ///     let proc = PtyProcess::spawn_matching_terminal(Command::new("vim"), 0);
+    /// ```
+    pub fn spawn_matching_terminal(command: Command, term_fd: RawFd) -> Result<Self> {
+        let mut builder = PtyProcessBuilder::new();
+        if let Ok((cols, rows)) = get_term_size(term_fd) {
+            builder = builder.window_size(cols, rows);
+        }
 
-                let eof_char = get_eof_char();
-                let intr_char = get_intr_char();
+        builder.spawn(command)
+    }
 
-                Ok(Self {
-                    master,
-                    child_pid: child,
-                    eof_char,
-                    intr_char,
-                    terminate_delay: DEFAULT_TERMINATE_DELAY,
-                })
-            }
-        }
+    /// Returns a slave side of a pty held by [PtyProcess].
+    ///
+    /// It's [Some] for a process created by [Self::from_pair]
+    /// or spawned with [PtyProcessBuilder::keep_slave].
+    ///
+    /// While the slave is held reads never see EOF,
+    /// as it's reported only once every slave handle is closed.
+    pub fn slave_fd(&self) -> Option<RawFd> {
+        self.slave.as_ref().map(AsRawFd::as_raw_fd)
     }
 
     /// Returns a pid of a child process
@@ -183,6 +267,13 @@ impl PtyProcess {
         self.child_pid
     }
 
+    /// Returns a pid of a child process as a plain integer.
+    ///
+    /// It doesn't depend on a version of `nix`.
+    pub fn raw_pid(&self) -> i32 {
+        self.child_pid.as_raw()
+    }
+
     /// Returns a file representation of a PTY, which can be used
     /// to communicate with a spawned process.
     ///
@@ -196,6 +287,8 @@ impl PtyProcess {
     /// Be carefull using this method in async mode.
     /// Because descriptor is set to a non-blocking mode will affect all dublicated descriptors
     /// which may be unexpected.
+    /// A master side can't be reopened to get an independent descriptor,
+    /// so consider using `poll` (e.g. [Self::wait_for_first_output]) instead of `O_NONBLOCK`.
     ///
     /// # Example
     ///
@@ -212,6 +305,81 @@ impl PtyProcess {
         self.master.get_file_handle()
     }
 
+    /// Returns a file intended for writing to a child, e.g. from a feeder thread.
+    ///
+    /// It's a duplicate of the same descriptor as [Self::get_raw_handle],
+    /// so the same precautions apply.
+    /// A pty is bidirectional so the file can technically be read from,
+    /// but it shouldn't be used that way as it would steal the child's output.
+    pub fn writer(&self) -> io::Result<File> {
+        Ok(self.get_raw_handle()?)
+    }
+
+    /// Splits a process into a file for IO and a [Controller] of its lifecycle.
+    ///
+    /// They can be moved to different threads.
+    /// The file is a duplicate descriptor, see [Self::get_raw_handle].
+    /// The child is terminated when the [Controller] is dropped.
+    pub fn handle_and_controller(mut self) -> Result<(File, Controller)> {
+        let file = self.get_raw_handle()?;
+
+        // a slave we hold would prevent the file from reaching EOF
+        self.slave = None;
+
+        Ok((file, Controller { process: self }))
+    }
+
+    /// Opens a slave side of a pty anew, the same way a child does.
+    ///
+    /// Unlike handles returned by [Self::get_raw_handle] and [Self::get_pty_stream]
+    /// it doesn't share an open file description with a master,
+    /// so its blocking mode (`O_NONBLOCK`) is isolated from other handles.
+    ///
+    /// Keep in mind that it's the *slave* side, the one a child uses:
+    /// what is written to it shows up as the child's output
+    /// and reads from it compete with the child for its input.
+    /// It's not a replacement for a master handle,
+    /// and while it's open a master never sees EOF.
+    pub fn open_independent_handle(&self) -> Result<Stream> {
+        let slave = open_parent_slave(&self.master)?;
+        Ok(Stream::new(File::from(slave)))
+    }
+
+    /// Splits a pty into independent reader and writer halves.
+    ///
+    /// Each half owns its own duplicate of a master descriptor,
+    /// so they can be moved to different threads.
+    /// A reader treats `EIO` as EOF the same way as [Stream] does.
+    pub fn split(&self) -> Result<(PtyReader, PtyWriter)> {
+        let reader = PtyReader {
+            inner: self.get_pty_stream()?,
+        };
+        let writer = PtyWriter {
+            inner: self.get_raw_handle()?,
+        };
+
+        Ok((reader, writer))
+    }
+
+    /// Splits a pty into asynchronous reader and writer halves for the tokio runtime.
+    ///
+    /// It's available with the `tokio` feature and must be called within a runtime.
+    ///
+    /// Each half owns its own duplicate of a master descriptor, the same way as [Self::split],
+    /// so they can be moved to different tasks.
+    /// A master is switched to non-blocking mode, see [Self::get_tokio_stream].
+    #[cfg(feature = "tokio")]
+    pub fn split_async(&self) -> io::Result<(AsyncPtyReader, AsyncPtyWriter)> {
+        let reader = AsyncPtyReader {
+            inner: self.get_tokio_stream()?,
+        };
+        let writer = AsyncPtyWriter {
+            inner: self.get_tokio_stream()?,
+        };
+
+        Ok((reader, writer))
+    }
+
     /// Returns a stream representation of a PTY.
     /// Which can be used to communicate with a spawned process.
     ///
@@ -221,161 +389,2581 @@ impl PtyProcess {
         self.get_raw_handle().map(Stream::new)
     }
 
-    /// Get a end of file character if set or a default.
-    pub fn get_eof_char(&self) -> u8 {
-        self.eof_char
-    }
+    /// Returns an asynchronous stream of a PTY for the tokio runtime.
+    ///
+    /// It's available with the `tokio` feature and must be called within a runtime.
+    ///
+    /// A master is switched to non-blocking mode (`O_NONBLOCK`),
+    /// which affects all its duplicates, see [Self::get_raw_handle].
+    #[cfg(feature = "tokio")]
+    pub fn get_tokio_stream(&self) -> io::Result<stream::TokioStream> {
+        stream::TokioStream::new(self.get_pty_stream()?)
+    }
+
+    /// Writes all the bytes to a pty and waits until they are passed to a terminal.
+    ///
+    /// Writes to a pty are not buffered in userspace,
+    /// neither here nor in a [Stream] or a handle returned by [Self::get_raw_handle],
+    /// so there's no need to call `flush` after a write.
+    /// This function additionally calls [Self::sync_input].
+    pub fn send_now(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.get_raw_handle()?.write_all(bytes)?;
+        self.sync_input()?;
+        Ok(())
+    }
+
+    /// Answers a device attributes request (DA1, `ESC [ c`) as a VT100 with advanced video.
+    ///
+    /// Some programs ask a terminal what it is on startup
+    /// and block until a response arrives, which a scripted driver never sends.
+    /// The request can be seen in an output, but it's up to a caller to look for it.
+    ///
+    /// Any other response can be sent by [Self::send_now].
+    pub fn answer_device_attributes(&mut self) -> io::Result<()> {
+        self.send_now(DEVICE_ATTRIBUTES_RESPONSE)
+    }
+
+    /// Answers a cursor position request (DSR, `ESC [ 6 n`).
+    ///
+    /// Row and column are 1-based.
+    pub fn answer_cursor_position(&mut self, row: u16, col: u16) -> io::Result<()> {
+        self.send_now(format!("\x1b[{};{}R", row, col).as_bytes())
+    }
+
+    /// Waits until everything written to a pty is transmitted to a child.
+    ///
+    /// As writes are not buffered in userspace there's nothing to flush,
+    /// so it only calls `tcdrain` on a master, which blocks until the transmission completes.
+    ///
+    /// It doesn't mean that the child has already read or processed the input.
+    pub fn sync_input(&self) -> Result<()> {
+        termios::tcdrain(self.master.as_raw_fd())
+    }
+
+    /// Drain is an alias to [Self::sync_input].
+    ///
+    /// It blocks until everything written is transmitted to a child,
+    /// e.g. before terminal settings are changed.
+    pub fn drain(&self) -> Result<()> {
+        self.sync_input()
+    }
+
+    /// Sends a BREAK condition via `tcsendbreak`.
+    ///
+    /// A pty has no line to hold in a space state so a BREAK is rather symbolic,
+    /// but it lets code written against a serial line keep working.
+    ///
+    /// A duration is platform dependent.
+    /// On Linux [None] means the default 0.25-0.5 seconds and a given duration is rounded up
+    /// to tenths of a second, while on BSD and macOS the duration is ignored
+    /// and a BREAK lasts for 0.4 seconds.
+    pub fn send_break(&mut self, duration_ms: Option<u16>) -> Result<()> {
+        let duration = duration_ms.map_or(0, libc::c_int::from);
+        termios::tcsendbreak(self.master.as_raw_fd(), duration)
+    }
+
+    /// Discards an input written to a child but not read by it yet,
+    /// e.g. an incomplete line in canonical mode.
+    ///
+    /// It opens a slave side of a pty for a moment,
+    /// as pending input is kept by the slave.
+    pub fn flush_input(&mut self) -> Result<()> {
+        let slave = open_parent_slave(&self.master)?;
+        termios::tcflush(slave.as_raw_fd(), termios::FlushArg::TCIFLUSH)
+    }
+
+    /// Discards an output of a child which was not read yet.
+    pub fn flush_output(&mut self) -> Result<()> {
+        // It's not a mistake: a child's output is an input queue of a master,
+        // so it's TCIFLUSH which discards it (TCOFLUSH on a master affects the child's input).
+        termios::tcflush(self.master.as_raw_fd(), termios::FlushArg::TCIFLUSH)
+    }
+
+    /// Discards both pending input and output, see [Self::flush_input] and [Self::flush_output].
+    pub fn flush_all(&mut self) -> Result<()> {
+        self.flush_input()?;
+        self.flush_output()
+    }
+
+    /// Waits until a child produces any output.
+    ///
+    /// It doesn't consume the output.
+    /// It returns true if there's something to read and false if the timeout is reached.
+    ///
+    /// It can be used to wait for a prompt before interacting with a child.
+    pub fn wait_for_first_output(&self, timeout: Duration) -> io::Result<bool> {
+        self.poll_readable(timeout)
+            .map(|revents| revents.contains(PollFlags::POLLIN))
+    }
+
+    /// Reads an output until a `prompt` appears, which doesn't have to end with a newline.
+    ///
+    /// It returns the output preceding the prompt.
+    /// The prompt itself is consumed.
+    ///
+    /// It never reads further than the prompt could end,
+    /// so nothing after the prompt is consumed.
+    ///
+    /// A [io::ErrorKind::TimedOut] error is returned if the prompt doesn't appear within `timeout`
+    /// and [io::ErrorKind::UnexpectedEof] if the child closes a pty before that.
+    pub fn read_until_prompt(&mut self, prompt: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let now = time::Instant::now();
+        while !output.ends_with(prompt) {
+            let left = timeout.saturating_sub(now.elapsed());
+            // a hangup means a read returns EOF right away
+            let revents = self.poll_readable(left)?;
+            if !revents.intersects(PollFlags::POLLIN | PollFlags::POLLHUP) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "prompt wasn't found",
+                ));
+            }
+
+            let start = output.len();
+            output.resize(start + prompt_missing_len(&output, prompt), 0);
+            let n = match unistd::read(self.master.as_raw_fd(), &mut output[start..]) {
+                Ok(n) => n,
+                // Linux returns EIO once a slave is closed
+                Err(Error::EIO) => 0,
+                Err(Error::EINTR) => {
+                    output.truncate(start);
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            output.truncate(start + n);
+        }
+
+        output.truncate(output.len() - prompt.len());
+
+        Ok(output)
+    }
+
+    /// Reads from a pty waiting for data not longer than `timeout`.
+    ///
+    /// See [Stream::read_timeout].
+    pub fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        self.get_pty_stream()?.read_timeout(buf, timeout)
+    }
+
+    /// Reads until EOF but not longer than `timeout`.
+    ///
+    /// It returns the number of bytes appended to `buf`.
+    ///
+    /// A [io::ErrorKind::TimedOut] error is returned if EOF isn't reached within `timeout`,
+    /// in which case `buf` still contains everything read so far.
+    pub fn read_to_end_timeout(
+        &mut self,
+        buf: &mut Vec<u8>,
+        timeout: Duration,
+    ) -> io::Result<usize> {
+        let mut stream = self.get_pty_stream()?;
+        let mut chunk = [0; 4096];
+        let start = buf.len();
+        let now = time::Instant::now();
+        loop {
+            let left = timeout.saturating_sub(now.elapsed());
+            let revents = self.poll_readable(left)?;
+            if !revents.intersects(PollFlags::POLLIN | PollFlags::POLLHUP) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("EOF wasn't reached, {} bytes were read", buf.len() - start),
+                ));
+            }
+
+            match stream.read(&mut chunk)? {
+                0 => return Ok(buf.len() - start),
+                n => buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+    }
+
+    /// Reads an output line by line and calls `f` for each line until EOF, then reaps the child.
+    ///
+    /// Lines are split on `\n` and a trailing `\r` is stripped.
+    /// A last line without a newline is passed as well.
+    ///
+    /// If `f` breaks the child is terminated by [Self::exit],
+    /// and a [io::ErrorKind::TimedOut] error is returned if it's still alive after that
+    /// (e.g. it's in uninterruptible sleep).
+    ///
+    /// A [io::ErrorKind::TimedOut] error is returned if EOF isn't reached within `timeout`.
+    pub fn for_each_line(
+        &mut self,
+        timeout: Duration,
+        mut f: impl FnMut(&str) -> ControlFlow<()>,
+    ) -> io::Result<WaitStatus> {
+        let mut stream = self.get_pty_stream()?;
+        let mut chunk = [0; 4096];
+        let mut line = Vec::new();
+        let now = time::Instant::now();
+        loop {
+            let left = timeout.saturating_sub(now.elapsed());
+            let revents = self.poll_readable(left)?;
+            if !revents.intersects(PollFlags::POLLIN | PollFlags::POLLHUP) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "EOF wasn't reached in time",
+                ));
+            }
+
+            let n = stream.read(&mut chunk)?;
+            if n == 0 {
+                if !line.is_empty() {
+                    let _ = f(&String::from_utf8_lossy(&line));
+                }
+
+                return Ok(self.wait()?);
+            }
+
+            for &b in &chunk[..n] {
+                if b != b'\n' {
+                    line.push(b);
+                    continue;
+                }
+
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+
+                let flow = f(&String::from_utf8_lossy(&line));
+                line.clear();
+
+                if flow.is_break() {
+                    if !self.exit(true)? {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "the child wasn't terminated",
+                        ));
+                    }
+
+                    // the child is reaped by exit, unless it was marked as exited
+                    return match (self.external_status, self.last_status_raw()) {
+                        (Some(status), _) => Ok(status),
+                        (None, Some(status)) => Ok(WaitStatus::from_raw(self.child_pid, status)?),
+                        (None, None) => Err(Error::ECHILD.into()),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Reads everything until EOF, but not more than `max` bytes if it's set.
+    ///
+    /// Once `max` is reached the output read so far is returned,
+    /// and the rest is left in a pty.
+    /// `EIO` is treated as EOF, see [Stream::set_eio_as_eof].
+    pub fn read_all(&mut self, max: Option<usize>) -> io::Result<Vec<u8>> {
+        let mut stream = self.get_pty_stream()?;
+        let max = match max {
+            Some(max) => max,
+            None => return stream.read_all(),
+        };
+
+        let mut output = Vec::new();
+        let mut buf = [0; 8 * 1024];
+        while output.len() < max {
+            let n = buf.len().min(max - output.len());
+            match stream.read(&mut buf[..n]) {
+                Ok(0) => break,
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Reads at most `max_bytes` or until the timeout is reached, whichever comes first.
+    ///
+    /// Everything beyond the limit is left in a pty for subsequent reads.
+    /// It also returns earlier if the child closes a pty.
+    pub fn read_limited(&mut self, max_bytes: usize, timeout: Duration) -> io::Result<Vec<u8>> {
+        let mut stream = self.get_pty_stream()?;
+        let mut output = Vec::new();
+        let mut buf = [0; 4096];
+        let now = time::Instant::now();
+        while output.len() < max_bytes {
+            let left = timeout.saturating_sub(now.elapsed());
+            let revents = self.poll_readable(left)?;
+            if !revents.intersects(PollFlags::POLLIN | PollFlags::POLLHUP) {
+                break;
+            }
+
+            let n = buf.len().min(max_bytes - output.len());
+            match stream.read(&mut buf[..n])? {
+                0 => break,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Sends a line followed by `\n`.
+    pub fn send_line(&mut self, line: &str) -> io::Result<()> {
+        self.send_line_with(line, b"\n")
+    }
+
+    /// Sends a line followed by a given line ending, e.g. `\r`.
+    ///
+    /// The whole line is sent by a single write, see [Self::send_now].
+    pub fn send_line_with(&mut self, line: &str, ending: &[u8]) -> io::Result<()> {
+        let mut bytes = line.as_bytes().to_vec();
+        bytes.extend_from_slice(ending);
+        self.send_now(&bytes)
+    }
+
+    /// Sends a line and consumes its echo if echo is on.
+    ///
+    /// See [Self::send_and_skip_echo].
+    pub fn send_line_skip_echo(&mut self, line: &str, timeout: Duration) -> io::Result<()> {
+        self.send_and_skip_echo(&format!("{}\n", line), timeout)
+    }
+
+    /// Sends a string and consumes its echo if echo is on.
+    ///
+    /// After it returns a next read starts with the child's own output.
+    /// An output which appeared before the echo is discarded.
+    ///
+    /// Each echoed newline is expected to be `\r\n` if `ONLCR` is set.
+    pub fn send_and_skip_echo(&mut self, s: &str, timeout: Duration) -> io::Result<()> {
+        let flags = self.flags_snapshot()?;
+
+        self.send_now(s.as_bytes())?;
+
+        if !flags.local_flags.contains(LocalFlags::ECHO) {
+            return Ok(());
+        }
+
+        let mut echo = Vec::with_capacity(s.len());
+        for &b in s.as_bytes() {
+            if b == b'\n' && flags.output_flags.contains(OutputFlags::ONLCR) {
+                echo.push(b'\r');
+            }
+            echo.push(b);
+        }
+
+        self.read_until_prompt(&echo, timeout)?;
+
+        Ok(())
+    }
+
+    /// Discards a child's startup output, e.g. a shell banner.
+    ///
+    /// It reads and drops an output until the child doesn't produce anything for `timeout`.
+    /// Returns the number of discarded bytes.
+    ///
+    /// A child is spawned as a foreground process group of its controlling terminal,
+    /// so shells don't complain about job control.
+    /// To reduce startup noise further run a shell as a non-login one.
+    pub fn drain_startup(&mut self, timeout: Duration) -> io::Result<usize> {
+        let mut stream = self.get_pty_stream()?;
+        let mut buf = [0; 4096];
+        let mut n = 0;
+        while self.wait_for_first_output(timeout)? {
+            match stream.read(&mut buf)? {
+                0 => break,
+                read => n += read,
+            }
+        }
+
+        Ok(n)
+    }
+
+    /// Checks a pty readiness and a child liveness at once.
+    ///
+    /// It blocks for at most `timeout` until any of the events happen.
+    /// Keep in mind that a pty is usually writable so it rarely blocks.
+    ///
+    /// On Linux 5.3+ a child exit is observed via a `pidfd` within the same `poll` call,
+    /// and the child is not reaped.
+    /// On other platforms the child status is checked by [Self::is_alive] after `poll`,
+    /// which may reap it.
+    pub fn poll(&self, timeout: Duration) -> Result<PollResult> {
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+        let mut fds = vec![PollFd::new(
+            self.master.as_raw_fd(),
+            PollFlags::POLLIN | PollFlags::POLLOUT,
+        )];
+        if let Some(pidfd) = &self.pidfd {
+            fds.push(PollFd::new(pidfd.as_raw_fd(), PollFlags::POLLIN));
+        }
+
+        poll(&mut fds, timeout)?;
+
+        let revents = fds[0].revents().unwrap_or_else(PollFlags::empty);
+        let exited = match fds.get(1) {
+            Some(pidfd) if self.external_status.is_none() => pidfd
+                .revents()
+                .unwrap_or_else(PollFlags::empty)
+                .contains(PollFlags::POLLIN),
+            _ => !self.is_alive()?,
+        };
+
+        Ok(PollResult {
+            readable: revents.contains(PollFlags::POLLIN),
+            writable: revents.contains(PollFlags::POLLOUT),
+            hangup: revents.contains(PollFlags::POLLHUP),
+            exited,
+        })
+    }
+
+    /// Checks whether no one holds a slave side of a pty anymore.
+    ///
+    /// It's a heuristic based on `POLLHUP` reported for a master,
+    /// which lets a caller tell whether an EOF is caused by the slave being closed
+    /// rather than by the child being gone.
+    ///
+    /// It's platform dependent, e.g. some platforms report a hangup only after a slave was opened once,
+    /// and it's never true while a slave given to [Self::from_pair] is held.
+    pub fn slave_refcount_zero(&self) -> Result<bool> {
+        let mut fds = [PollFd::new(self.master.as_raw_fd(), PollFlags::POLLIN)];
+        poll(&mut fds, 0)?;
+
+        let revents = fds[0].revents().unwrap_or_else(PollFlags::empty);
+        Ok(revents.contains(PollFlags::POLLHUP))
+    }
+
+    /// Checks whether a slave side is closed and there's nothing left to read,
+    /// so a next read is guaranteed to return EOF.
+    ///
+    /// It's based on `POLLHUP` same as [Self::slave_refcount_zero],
+    /// but it's false while an output is still buffered.
+    pub fn is_hung_up(&self) -> Result<bool> {
+        let revents = self
+            .poll_readable(Duration::ZERO)
+            .map_err(io_error_to_errno)?;
+
+        Ok(revents.contains(PollFlags::POLLHUP) && !revents.contains(PollFlags::POLLIN))
+    }
+
+    /// Reads everything from a pty until EOF and writes it to `out`.
+    ///
+    /// It works like [std::io::copy] but handles `EIO` as an EOF.
+    /// Returns the total number of bytes copied.
+    pub fn pipe_to(&mut self, out: &mut impl Write) -> io::Result<u64> {
+        let mut stream = self.get_pty_stream()?;
+        io::copy(&mut stream, out)
+    }
+
+    /// Writes `input` to a child while reading its output into `output` until EOF.
+    ///
+    /// Input is written as the child becomes ready to accept it,
+    /// and an EOF character is sent once it's exhausted, the same way as [Self::run_with_input] does.
+    /// If the child exits before reading all of the input the rest of it is dropped.
+    /// It returns the total number of output bytes.
+    ///
+    /// `input` is read from the calling thread, so it shouldn't block for long.
+    /// A master is switched to non-blocking mode for the time of the call,
+    /// which affects its duplicates (e.g. [Self::get_raw_handle]) as well.
+    ///
+    /// EOF is reached once the child closes a pty, usually by exiting,
+    /// but the child is not reaped.
+    pub fn pipe<R, W>(&mut self, mut input: R, mut output: W) -> io::Result<u64>
+    where
+        R: Read,
+        W: Write,
+    {
+        self.exchange(&mut input, &mut output, None)
+    }
+
+    /// Reads an output which is already buffered in a pty without blocking.
+    ///
+    /// It stops when nothing more is available at the moment or on EOF;
+    /// `EIO` which Linux returns once a slave is closed is treated as EOF.
+    ///
+    /// On some platforms (e.g. macOS, FreeBSD) a buffered output is lost once a child is reaped,
+    /// so it must be called before [Self::wait] or [Self::status] to behave the same everywhere.
+    pub fn drain_remaining(&mut self) -> io::Result<Vec<u8>> {
+        let mut stream = self.get_pty_stream()?;
+        let mut output = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let revents = self.poll_readable(Duration::ZERO)?;
+            if !revents.intersects(PollFlags::POLLIN | PollFlags::POLLHUP) {
+                return Ok(output);
+            }
+
+            match stream.read(&mut buf)? {
+                0 => return Ok(output),
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+    }
+
+    /// Reads a remaining output of a child and waits for it to exit.
+    ///
+    /// The output is drained before the child is reaped,
+    /// because on some platforms (e.g. macOS, FreeBSD) it's lost once the child is reaped.
+    ///
+    /// Keep in mind that it blocks until every process which holds a slave side is gone,
+    /// e.g. a background process started by a child.
+    pub fn finish(&mut self) -> Result<(Vec<u8>, WaitStatus)> {
+        // a slave we hold would prevent reaching EOF
+        self.slave = None;
+
+        let mut output = Vec::new();
+        self.get_pty_stream()?
+            .read_to_end(&mut output)
+            .map_err(io_error_to_errno)?;
+
+        let status = self.wait()?;
+
+        Ok((output, status))
+    }
+
+    /// Writes escape sequences which disable terminal modes a child may have enabled.
+    ///
+    /// It's useful to restore a host terminal after proxying a child's output to it,
+    /// e.g. if the child exited abnormally.
+    ///
+    /// The following sequences are emitted:
+    ///
+    /// - `\x1b[?1000l` - disable mouse reporting (X11)
+    /// - `\x1b[?1002l` - disable button-event mouse tracking
+    /// - `\x1b[?1003l` - disable any-event mouse tracking
+    /// - `\x1b[?1006l` - disable SGR extended mouse mode
+    /// - `\x1b[?2004l` - disable bracketed paste
+    /// - `\x1b[?1049l` - leave an alternate screen
+    /// - `\x1b[?25h` - show a cursor
+    pub fn emit_reset_sequences(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(TERMINAL_RESET_SEQUENCES)?;
+        out.flush()
+    }
+
+    /// Returns a stream of a child's stderr.
+    ///
+    /// It's available only if a process was spawned with
+    /// [PtyProcessBuilder::separate_stderr_pty], otherwise `EBADF` is returned.
+    pub fn stderr_stream(&self) -> Result<Stream> {
+        match &self.stderr_master {
+            Some(master) => master.get_file_handle().map(Stream::new),
+            None => Err(Error::EBADF),
+        }
+    }
+
+    /// Get a end of file character if set or a default.
+    pub fn get_eof_char(&self) -> u8 {
+        self.eof_char
+    }
+
+    /// Get a interapt character if set or a default.
+    pub fn get_intr_char(&self) -> u8 {
+        self.intr_char
+    }
+
+    /// Sets an end of file character of a terminal (`VEOF`).
+    pub fn set_eof_char(&mut self, c: u8) -> Result<()> {
+        set_term_char(self.master.as_raw_fd(), SpecialCharacterIndices::VEOF, c)?;
+        self.eof_char = c;
+        Ok(())
+    }
+
+    /// Sets an interrupt character of a terminal (`VINTR`).
+    pub fn set_intr_char(&mut self, c: u8) -> Result<()> {
+        set_term_char(self.master.as_raw_fd(), SpecialCharacterIndices::VINTR, c)?;
+        self.intr_char = c;
+        Ok(())
+    }
+
+    /// Get window size of a terminal.
+    ///
+    /// It returns `(cols, rows)`, in contrast to the order of `winsize` fields.
+    ///
+    /// Default size is 80x24.
+    pub fn get_window_size(&self) -> Result<(u16, u16)> {
+        get_term_size(self.master.as_raw_fd())
+    }
+
+    /// Sets a terminal size.
+    ///
+    /// A width (`cols`) goes first and a height (`rows`) second.
+    pub fn set_window_size(&mut self, cols: u16, rows: u16) -> Result<()> {
+        set_term_size(self.master.as_raw_fd(), cols, rows)?;
+        *self.last_window_size.lock().unwrap() = (cols, rows);
+        Ok(())
+    }
+
+    /// Sets a terminal size and sends `SIGWINCH` to a foreground process group.
+    ///
+    /// The kernel sends `SIGWINCH` on its own only if the size actually changes,
+    /// here it's sent unconditionally so full-screen programs repaint.
+    pub fn resize(&mut self, cols: u16, rows: u16) -> Result<()> {
+        self.set_window_size(cols, rows)?;
+
+        let pgrp = unistd::tcgetpgrp(self.master.as_raw_fd())?;
+        signal::killpg(pgrp, signal::SIGWINCH)
+    }
+
+    /// Checks whether a terminal has a given size.
+    pub fn assert_window_size(&self, cols: u16, rows: u16) -> Result<bool> {
+        self.get_window_size().map(|size| size == (cols, rows))
+    }
+
+    /// Checks whether a terminal size was changed since the last observation.
+    ///
+    /// It returns a new size if it differs from the last observed one,
+    /// which is either the size set by [PtyProcess::set_window_size]
+    /// or the one returned by a previous call to this function.
+    ///
+    /// It's useful to detect changes made by a child process or the kernel.
+    pub fn poll_window_size(&self) -> Result<Option<(u16, u16)>> {
+        let size = get_term_size(self.master.as_raw_fd())?;
+        let mut last = self.last_window_size.lock().unwrap();
+        if *last == size {
+            return Ok(None);
+        }
+
+        *last = size;
+        Ok(Some(size))
+    }
+
+    /// The function returns true if an echo setting is setup.
+    pub fn get_echo(&self) -> Result<bool> {
+        termios::tcgetattr(self.master.as_raw_fd())
+            .map(|flags| flags.local_flags.contains(termios::LocalFlags::ECHO))
+    }
+
+    /// Returns a snapshot of terminal flags.
+    ///
+    /// It can be modified and set back by [Self::apply].
+    pub fn flags_snapshot(&self) -> Result<TermiosFlags> {
+        termios::tcgetattr(self.master.as_raw_fd()).map(|flags| TermiosFlags {
+            input_flags: flags.input_flags,
+            output_flags: flags.output_flags,
+            control_flags: flags.control_flags,
+            local_flags: flags.local_flags,
+        })
+    }
+
+    /// Sets terminal flags.
+    ///
+    /// Other terminal settings, like special characters, are left untouched.
+    pub fn apply(&mut self, flags: &TermiosFlags) -> Result<()> {
+        let fd = self.master.as_raw_fd();
+        let mut termios = termios::tcgetattr(fd)?;
+        termios.input_flags = flags.input_flags;
+        termios.output_flags = flags.output_flags;
+        termios.control_flags = flags.control_flags;
+        termios.local_flags = flags.local_flags;
+
+        termios::tcsetattr(fd, termios::SetArg::TCSANOW, &termios)
+    }
+
+    /// Returns how a pty delivers input to a child.
+    pub fn read_mode(&self) -> Result<ReadMode> {
+        let flags = termios::tcgetattr(self.master.as_raw_fd())?;
+        if flags.local_flags.contains(LocalFlags::ICANON) {
+            return Ok(ReadMode::Canonical);
+        }
+
+        Ok(ReadMode::NonCanonical {
+            min: flags.control_chars[SpecialCharacterIndices::VMIN as usize],
+            time: flags.control_chars[SpecialCharacterIndices::VTIME as usize],
+        })
+    }
+
+    /// Sets an echo setting, runs `f` and restores the previous setting.
+    ///
+    /// The setting is restored even if `f` panics.
+    pub fn with_echo<F, R>(&mut self, on: bool, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let fd = self.master.as_raw_fd();
+        let _restore = EchoRestore {
+            fd,
+            on: self.get_echo()?,
+        };
+
+        set_echo(fd, on)?;
+
+        Ok(f(self))
+    }
+
+    /// Returns true if bytes written to a pty are echoed back to its output.
+    ///
+    /// When echo is on everything sent to a child will be read back
+    /// before (or interleaved with) the child's own output,
+    /// so a read loop must expect its own input.
+    ///
+    /// It's derived from the `ECHO` flag, see [Self::get_echo].
+    pub fn writes_are_echoed(&self) -> Result<bool> {
+        self.get_echo()
+    }
+
+    /// The function returns true if an echo setting is turned off.
+    ///
+    /// It's an inverse of [Self::get_echo].
+    pub fn echo_is_off(&self) -> Result<bool> {
+        self.get_echo().map(|on| !on)
+    }
+
+    /// Checks whether it's safe to send a secret, e.g. a password.
+    ///
+    /// It returns true if echo is turned off, so the secret won't appear in an output.
+    ///
+    /// Keep in mind that it only checks a terminal setting.
+    /// A child may still print what it reads on its own.
+    pub fn ready_for_secret(&self) -> Result<bool> {
+        self.echo_is_off()
+    }
+
+    /// Sets a echo setting for a terminal
+    pub fn set_echo(&mut self, on: bool, timeout: Option<Duration>) -> Result<bool> {
+        set_echo(self.master.as_raw_fd(), on)?;
+        self.wait_echo(on, timeout)
+    }
+
+    /// Puts a terminal into raw mode, see [set_raw].
+    ///
+    /// Input is passed to a child byte by byte, without line editing and signal generation,
+    /// and output is not processed.
+    /// `VMIN` is set to 1 and `VTIME` to 0, so a child's read returns as soon as a byte is available.
+    ///
+    /// Raw mode turns echo off as well, it can be turned back by [Self::set_echo].
+    pub fn set_raw(&mut self) -> Result<()> {
+        set_raw(self.master.as_raw_fd())
+    }
+
+    /// Turns packet mode (`TIOCPKT`) of a master on or off.
+    ///
+    /// In packet mode every read from a master starts with a control byte,
+    /// which is 0 (`TIOCPKT_DATA`) if the rest is data,
+    /// or a combination of flags describing a change in a slave's line discipline,
+    /// e.g. flushed queues or stopped output.
+    /// Use [Self::read_packet] to read in this mode.
+    #[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
+    pub fn set_packet_mode(&mut self, on: bool) -> Result<()> {
+        ioctl_write_ptr_bad!(_set_packet_mode, libc::TIOCPKT, libc::c_int);
+
+        let on = libc::c_int::from(on);
+        let _ = unsafe { _set_packet_mode(self.master.as_raw_fd(), &on) }?;
+
+        Ok(())
+    }
+
+    /// Reads a packet when packet mode is on, see [Self::set_packet_mode].
+    ///
+    /// It returns flags of a control byte and the number of data bytes put in `buf`.
+    /// A data packet has no flags set,
+    /// while a control packet carries no data.
+    /// On EOF empty flags and 0 are returned.
+    #[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
+    pub fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<(PacketFlags, usize)> {
+        // a single read, as the control byte is only the first one of a packet
+        let mut control = [0];
+        let n = self
+            .get_pty_stream()?
+            .read_vectored(&mut [io::IoSliceMut::new(&mut control), io::IoSliceMut::new(buf)])?;
+        if n == 0 {
+            return Ok((PacketFlags::default(), 0));
+        }
+
+        Ok((PacketFlags::from_byte(control[0]), n - 1))
+    }
+
+    /// Returns true if a terminal is in raw mode.
+    ///
+    /// Raw here means that canonical mode (`ICANON`), echo (`ECHO`),
+    /// signal generation (`ISIG`) and output processing (`OPOST`) are all turned off,
+    /// which is what [Self::set_raw] does.
+    /// Other flags changed by `cfmakeraw` are not checked.
+    pub fn is_raw(&self) -> Result<bool> {
+        let flags = termios::tcgetattr(self.master.as_raw_fd())?;
+        let raw = !flags
+            .local_flags
+            .intersects(LocalFlags::ICANON | LocalFlags::ECHO | LocalFlags::ISIG)
+            && !flags.output_flags.contains(OutputFlags::OPOST);
+
+        Ok(raw)
+    }
+
+    /// Returns true if a terminal is configured as an interactive one.
+    ///
+    /// It means that echo, canonical mode and signal generation are all enabled.
+    pub fn is_interactive(&self) -> Result<bool> {
+        termios::tcgetattr(self.master.as_raw_fd()).map(|flags| {
+            flags.local_flags.contains(
+                termios::LocalFlags::ECHO | termios::LocalFlags::ICANON | termios::LocalFlags::ISIG,
+            )
+        })
+    }
+
+    /// Sets or clears close-on-exec flag (`FD_CLOEXEC`) of a master.
+    ///
+    /// A master is opened with the flag set,
+    /// so it's not inherited by programs started by the current process.
+    /// Handles returned by [Self::get_raw_handle] and [Self::get_pty_stream] have their own flag.
+    pub fn set_cloexec(&mut self, on: bool) -> Result<()> {
+        set_cloexec(self.master.as_raw_fd(), on)
+    }
+
+    /// Returns true if a underline `fd` connected with a TTY.
+    pub fn isatty(&self) -> Result<bool> {
+        isatty(self.master.as_raw_fd())
+    }
+
+    /// Returns true if a child is a session leader.
+    ///
+    /// It's expected to be true for a spawned child, as it calls `setsid`.
+    /// An error is returned if the child is gone.
+    pub fn is_session_leader(&self) -> Result<bool> {
+        unistd::getsid(Some(self.child_pid)).map(|sid| sid == self.child_pid)
+    }
+
+    /// Returns a name of a command which is a foreground process group of a terminal.
+    ///
+    /// It can be used to determine whether a shell is waiting on a prompt
+    /// or runs a subprocess.
+    ///
+    /// The name is read from `/proc/<pgrp>/comm`,
+    /// so an error is returned if `/proc` is not available.
+    #[cfg(target_os = "linux")]
+    pub fn foreground_command(&self) -> Result<String> {
+        let pgrp = unistd::tcgetpgrp(self.master.as_raw_fd())?;
+        let comm =
+            std::fs::read_to_string(format!("/proc/{}/comm", pgrp)).map_err(io_error_to_errno)?;
+
+        Ok(comm.trim_end().to_owned())
+    }
+
+    /// Returns a path of a controlling terminal of a child process.
+    ///
+    /// It's read from `/proc/<pid>/stat`.
+    /// [None] is returned if the child has no controlling terminal
+    /// or it's not a pseudo terminal.
+    #[cfg(target_os = "linux")]
+    pub fn child_controlling_tty(&self) -> Result<Option<String>> {
+        let tty_nr: u32 = read_proc_stat_field(self.child_pid, 7)?;
+
+        let major = (tty_nr >> 8) & 0xfff;
+        let minor = (tty_nr & 0xff) | ((tty_nr >> 12) & 0xfff00);
+
+        // Unix98 pty slaves use majors 136-143.
+        match major {
+            136..=143 => Ok(Some(format!("/dev/pts/{}", (major - 136) * 256 + minor))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Returns a memory and CPU usage of a child process.
+    ///
+    /// It's read from `/proc/<pid>/stat` and `/proc/<pid>/statm`.
+    #[cfg(target_os = "linux")]
+    pub fn resource_usage(&self) -> Result<ResourceUsage> {
+        let ticks = sysconf(SysconfVar::CLK_TCK)?.ok_or(Error::EINVAL)? as u64;
+        let page_size = sysconf(SysconfVar::PAGE_SIZE)?.ok_or(Error::EINVAL)? as u64;
+
+        let utime: u64 = read_proc_stat_field(self.child_pid, 14)?;
+        let stime: u64 = read_proc_stat_field(self.child_pid, 15)?;
+
+        let statm = std::fs::read_to_string(format!("/proc/{}/statm", self.child_pid))
+            .map_err(io_error_to_errno)?;
+        let resident_pages: u64 = statm
+            .split_whitespace()
+            .nth(1)
+            .and_then(|pages| pages.parse().ok())
+            .ok_or(Error::EINVAL)?;
+
+        let ticks_to_duration = |t: u64| Duration::from_nanos(t * 1_000_000_000 / ticks);
+
+        Ok(ResourceUsage {
+            rss: resident_pages * page_size,
+            user_time: ticks_to_duration(utime),
+            system_time: ticks_to_duration(stime),
+        })
+    }
+
+    /// Set the pty process's terminate approach delay.
+    pub fn set_terminate_delay(&mut self, terminate_approach_delay: Duration) {
+        self.terminate_delay = terminate_approach_delay;
+    }
+
+    /// Status returns a status a of child process.
+    pub fn status(&self) -> Result<WaitStatus> {
+        self.waitpid(Some(wait::WaitPidFlag::WNOHANG))
+    }
+
+    /// Returns a raw status of a child process observed by the last [Self::status] or [Self::wait] call.
+    ///
+    /// It's a value set by `waitpid` so it can be serialized
+    /// and interpreted later on by `WIFEXITED`, `WEXITSTATUS` etc.
+    ///
+    /// It returns [None] if the process has not been reaped yet.
+    pub fn last_status_raw(&self) -> Option<i32> {
+        *self.last_status.lock().unwrap()
+    }
+
+    /// Records an exit status of a child process which was observed outside of [PtyProcess].
+    ///
+    /// It must be used if a child was reaped by a `waitpid` call made by a caller.
+    /// After that [Self::status] and [Self::wait] return the recorded status
+    /// and [Drop] doesn't try to terminate the child.
+    pub fn mark_exited(&mut self, status: WaitStatus) {
+        self.external_status = Some(status);
+    }
+
+    /// Kill sends a signal to a child process.
+    ///
+    /// The operation is non-blocking.
+    ///
+    /// On Linux 5.3+ a signal is sent via a `pidfd`,
+    /// so it can't be delivered to another process which reused a pid of a reaped child.
+    pub fn kill(&mut self, signal: signal::Signal) -> Result<()> {
+        match &self.pidfd {
+            Some(pidfd) => pidfd_send_signal(pidfd, signal),
+            None => signal::kill(self.child_pid, signal),
+        }
+    }
+
+    /// Sends a signal by its name, e.g. `TERM`, `SIGKILL`, `int` or by its number, e.g. `9`.
+    ///
+    /// An `EINVAL` error is returned for an unknown signal.
+    pub fn kill_by_name(&mut self, name: &str) -> Result<()> {
+        let signal = parse_signal(name)?;
+        self.kill(signal)
+    }
+
+    /// Hangup sends `SIGHUP` to a foreground process group of a terminal.
+    ///
+    /// It simulates a terminal disconnect.
+    /// Unlike [PtyProcess::kill] it reaches all processes in the foreground group,
+    /// e.g. a job run by a shell, not only the child itself.
+    ///
+    /// Dropping [PtyProcess] closes the master which also results in `SIGHUP`
+    /// being sent by the kernel, but the terminal can't be used after that.
+    pub fn hangup(&mut self) -> Result<()> {
+        let pgrp = unistd::tcgetpgrp(self.master.as_raw_fd())?;
+        signal::killpg(pgrp, signal::SIGHUP)
+    }
+
+    /// Signal is an alias to [PtyProcess::kill].
+    ///
+    /// [PtyProcess::kill]: struct.PtyProcess.html#method.kill
+    pub fn signal(&mut self, signal: signal::Signal) -> Result<()> {
+        self.kill(signal)
+    }
+
+    /// Wait blocks until a child process exits.
+    ///
+    /// It returns a error if the child was DEAD or not exist
+    /// at the time of a call.
+    ///
+    /// If you need to verify that a process is dead in non-blocking way you can use
+    /// [is_alive] method.
+    ///
+    /// [is_alive]: struct.PtyProcess.html#method.is_alive
+    pub fn wait(&self) -> Result<WaitStatus> {
+        self.waitpid(None)
+    }
+
+    /// Waits for a child process to exit but not longer than `timeout`.
+    ///
+    /// It returns [None] if the child is still alive after the timeout.
+    /// The status is checked every 5 milliseconds,
+    /// see [Self::wait_timeout_with_interval] to change it.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<Option<WaitStatus>> {
+        self.wait_timeout_with_interval(timeout, EXIT_POLL_INTERVAL)
+    }
+
+    /// Waits for a child process to exit but not longer than `timeout`,
+    /// checking its status every `interval`.
+    ///
+    /// A shorter interval notices an exit sooner at the cost of more wakeups.
+    /// See [Self::wait_timeout].
+    pub fn wait_timeout_with_interval(
+        &self,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Option<WaitStatus>> {
+        let now = time::Instant::now();
+        loop {
+            let status = self.status()?;
+            if status != WaitStatus::StillAlive {
+                return Ok(Some(status));
+            }
+
+            let left = timeout.saturating_sub(now.elapsed());
+            if left.is_zero() {
+                return Ok(None);
+            }
+
+            thread::sleep(left.min(interval));
+        }
+    }
+
+    /// Returns a future which resolves to a child's exit status once it exits.
+    ///
+    /// It's available with the `tokio` feature and must be called within a runtime.
+    ///
+    /// On Linux 5.3+ the exit is observed via a `pidfd`, otherwise by a background thread
+    /// which waits for the exit without reaping the child.
+    /// The child is reaped only once the future resolves,
+    /// so dropping it beforehand is harmless and [Self::wait] still returns the status.
+    #[cfg(feature = "tokio")]
+    pub fn exited(&self) -> io::Result<ChildExit<'_>> {
+        let waiter = match &self.pidfd {
+            Some(pidfd) => {
+                let fd = fcntl(pidfd.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(0))?;
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                ExitWaiter::Pidfd(tokio::io::unix::AsyncFd::new(fd)?)
+            }
+            None => ExitWaiter::Thread(spawn_exit_waiter(self.child_pid)),
+        };
+
+        Ok(ChildExit {
+            process: self,
+            waiter,
+        })
+    }
+
+    /// Waits for a child process to exit and returns a description of how it exited.
+    ///
+    /// See [Self::wait].
+    pub fn wait_detailed(&self) -> Result<ExitInfo> {
+        let status = self.wait()?;
+
+        Ok(ExitInfo {
+            code: status_exit_code(&status),
+            signal: status_signal(&status),
+            core_dumped: status_core_dumped(&status),
+            raw: self.last_status_raw(),
+        })
+    }
+
+    /// Checks if a process is still exists.
+    ///
+    /// It's a non blocking operation.
+    ///
+    /// Keep in mind that after calling this method process might be marked as DEAD by kernel,
+    /// because a check of its status.
+    /// Therefore second call to [Self::status] or [Self::is_alive] might return a different status.
+    ///
+    /// On Linux 5.3+ a running child is determined via a `pidfd`
+    /// and only an exited one is reaped.
+    pub fn is_alive(&self) -> Result<bool> {
+        if self.external_status.is_none() {
+            if let Some(pidfd) = &self.pidfd {
+                if !pidfd_has_exited(pidfd)? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        let status = self.status();
+        match status {
+            Ok(WaitStatus::StillAlive) => Ok(true),
+            Ok(_) | Err(Error::ECHILD) | Err(Error::ESRCH) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Try to force a child to terminate.
+    ///
+    /// This returns true if the child was terminated. and returns false if the
+    /// child could not be terminated.
+    ///
+    /// It makes 4 tries getting more thorough.
+    /// After each signal it waits for at most a terminate delay
+    /// and returns as soon as the child is dead.
+    ///
+    /// 1. SIGHUP
+    /// 2. SIGCONT
+    /// 3. SIGINT
+    /// 4. SIGTERM
+    ///
+    /// If "force" is `true` then moves onto SIGKILL.
+    pub fn exit(&mut self, force: bool) -> Result<bool> {
+        self.terminate(force, None)
+    }
+
+    /// Prevents [Drop] from killing a child process while the returned guard is alive.
+    ///
+    /// Once all guards are dropped the default behaviour is restored.
+    ///
+    /// It may be usefull to hand a child process to a debugger or inspect it post-mortem.
+    ///
+    /// Notice that if [PtyProcess] is dropped while a guard is alive
+    /// the child process won't be killed nor reaped, so it will be leaked.
+    pub fn suppress_kill_on_drop(&mut self) -> KillSuppressionGuard {
+        KillSuppressionGuard::new(self.kill_on_drop_suppressions.clone())
+    }
+
+    // Polls a child status until it exits or the timeout is reached.
+    fn wait_for_exit(&self, timeout: Duration) -> Result<bool> {
+        let now = time::Instant::now();
+        loop {
+            if !self.is_alive()? {
+                return Ok(true);
+            }
+
+            if now.elapsed() >= timeout {
+                return Ok(false);
+            }
+
+            thread::sleep(EXIT_POLL_INTERVAL);
+        }
+    }
+
+    // Writes `input` followed by an EOF character to a child while reading its output until EOF.
+    //
+    // Everything is done from the calling thread with a master switched to non-blocking mode,
+    // so a child which stops reading its input (e.g. exits) can't block a write forever.
+    fn exchange(
+        &mut self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        deadline: Option<time::Instant>,
+    ) -> io::Result<u64> {
+        let fd = self.master.as_raw_fd();
+        let _nonblocking = NonBlockingGuard::new(fd)?;
+        let mut stream = self.get_pty_stream()?;
+
+        let mut chunk = [0; 4096];
+        let mut pending = Vec::new();
+        let mut written = 0;
+        let mut last = b'\n';
+        let mut input_done = false;
+        let mut writing = true;
+        let mut total = 0;
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(time::Instant::now());
+                    if left.is_zero() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("EOF wasn't reached, {} bytes were read", total),
+                        ));
+                    }
+
+                    left.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int
+                }
+                None => -1,
+            };
+
+            let events = match writing {
+                true => PollFlags::POLLIN | PollFlags::POLLOUT,
+                false => PollFlags::POLLIN,
+            };
+            let mut fds = [PollFd::new(fd, events)];
+            match poll(&mut fds, timeout) {
+                Ok(_) => {}
+                Err(Error::EINTR) => continue,
+                Err(err) => return Err(err.into()),
+            }
+            let revents = fds[0].revents().unwrap_or_else(PollFlags::empty);
+
+            let readable = PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR;
+            if revents.intersects(readable) {
+                match stream.read(&mut chunk) {
+                    Ok(0) => return Ok(total),
+                    Ok(n) => {
+                        output.write_all(&chunk[..n])?;
+                        total += n as u64;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(err) => return Err(err),
+                }
+            }
+
+            if !writing || !revents.contains(PollFlags::POLLOUT) {
+                continue;
+            }
+
+            if written == pending.len() {
+                pending.clear();
+                written = 0;
+
+                if input_done {
+                    writing = false;
+                    continue;
+                }
+
+                match input.read(&mut chunk) {
+                    Ok(0) => {
+                        input_done = true;
+
+                        // a not empty line must be flushed before EOF is seen
+                        if last != b'\n' {
+                            pending.push(self.eof_char);
+                        }
+                        pending.push(self.eof_char);
+                    }
+                    Ok(n) => {
+                        pending.extend_from_slice(&chunk[..n]);
+                        last = chunk[n - 1];
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => return Err(err),
+                }
+            }
+
+            match write(fd, &pending[written..]) {
+                Ok(n) => written += n,
+                Err(Error::EAGAIN) | Err(Error::EINTR) => {}
+                // the child has stopped reading, e.g. it's gone before reading all the input
+                Err(Error::EIO) | Err(Error::EPIPE) => writing = false,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // Polls a master for input, returns empty flags on timeout.
+    fn poll_readable(&self, timeout: Duration) -> io::Result<PollFlags> {
+        poll_readable(self.master.as_raw_fd(), timeout)
+    }
+
+    fn waitpid(&self, options: Option<wait::WaitPidFlag>) -> Result<WaitStatus> {
+        if let Some(status) = self.external_status {
+            return Ok(status);
+        }
+
+        let (status, raw) = waitpid_raw(self.child_pid, options)?;
+        if raw.is_some() {
+            *self.last_status.lock().unwrap() = raw;
+        }
+
+        Ok(status)
+    }
+
+    // The same as [Self::exit] but no wait lasts past a deadline if it's set.
+    fn terminate(&mut self, force: bool, deadline: Option<time::Instant>) -> Result<bool> {
+        if !self.is_alive()? {
+            return Ok(true);
+        }
+
+        for &signal in &[
+            signal::SIGHUP,
+            signal::SIGCONT,
+            signal::SIGINT,
+            signal::SIGTERM,
+        ] {
+            if self.try_to_terminate(signal, deadline)? {
+                return Ok(true);
+            }
+        }
+
+        if !force {
+            return Ok(false);
+        }
+
+        self.try_to_terminate(SIGKILL, deadline)
+    }
+
+    fn try_to_terminate(
+        &mut self,
+        signal: signal::Signal,
+        deadline: Option<time::Instant>,
+    ) -> Result<bool> {
+        self.kill(signal)?;
+
+        let delay = match deadline {
+            Some(deadline) => {
+                let left = deadline.saturating_duration_since(time::Instant::now());
+                self.terminate_delay.min(left)
+            }
+            None => self.terminate_delay,
+        };
+
+        self.wait_for_exit(delay)
+    }
+
+    fn wait_echo(&self, on: bool, timeout: Option<Duration>) -> Result<bool> {
+        let now = time::Instant::now();
+        while timeout.is_none() || now.elapsed() < timeout.unwrap() {
+            if on == self.get_echo()? {
+                return Ok(true);
+            }
+
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        Ok(false)
+    }
+}
+
+impl AsFd for PtyProcess {
+    /// Borrows a master side of a pty.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.master.as_fd()
+    }
+}
+
+impl Drop for PtyProcess {
+    fn drop(&mut self) {
+        if self.kill_on_drop_suppressions.load(Ordering::SeqCst) > 0 {
+            return;
+        }
+
+        if let Ok(WaitStatus::StillAlive) = self.status() {
+            match self.drop_signal {
+                Some((signal, timeout)) => {
+                    if self.kill(signal).is_ok() {
+                        let _ = self.wait_for_exit(timeout);
+                    }
+                }
+                None => {
+                    // A drop must not block for long whatever a terminate delay is,
+                    // so every step is bounded by a single deadline.
+                    let deadline = time::Instant::now() + DROP_TIMEOUT;
+
+                    // SIGKILL may not take effect right away (e.g. a child in uninterruptible sleep),
+                    // so it's always given some time to be reaped.
+                    let _ = self.terminate(false, Some(deadline - DROP_REAP_TIMEOUT));
+                    if let Ok(true) = self.is_alive() {
+                        if self.kill(SIGKILL).is_ok() {
+                            let left = deadline.saturating_duration_since(time::Instant::now());
+                            let _ = self.wait_for_exit(left);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_signal(name: &str) -> Result<Signal> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::try_from(number);
+    }
+
+    let name = name.to_ascii_uppercase();
+    let name = match name.starts_with("SIG") {
+        true => name,
+        false => format!("SIG{}", name),
+    };
+
+    name.parse()
+}
+
+fn clear_env_except(command: &mut Command, keep: &[String]) {
+    let explicit = command
+        .get_envs()
+        .filter_map(|(key, value)| value.map(|value| (key.to_owned(), value.to_owned())))
+        .collect::<Vec<_>>();
+
+    command.env_clear();
+
+    for name in keep {
+        if let Some(value) = std::env::var_os(name) {
+            command.env(name, value);
+        }
+    }
+
+    command.envs(explicit);
+}
+
+const LIBSTDBUF_PATHS: &[&str] = &[
+    "/usr/libexec/coreutils/libstdbuf.so",
+    "/usr/lib/coreutils/libstdbuf.so",
+    "/usr/lib/x86_64-linux-gnu/coreutils/libstdbuf.so",
+    "/usr/lib/aarch64-linux-gnu/coreutils/libstdbuf.so",
+    "/usr/local/libexec/coreutils/libstdbuf.so",
+];
+
+// Finds coreutils' `libstdbuf.so`, if it's not found only `_STDBUF_O` is set.
+fn libstdbuf_path() -> Option<&'static Path> {
+    LIBSTDBUF_PATHS
+        .iter()
+        .map(Path::new)
+        .find(|path| path.exists())
+}
+
+fn set_line_buffered_env(command: &mut Command) {
+    command.env("_STDBUF_O", "L");
+
+    let lib = match libstdbuf_path() {
+        Some(lib) => lib,
+        None => return,
+    };
+
+    let preload = command
+        .get_envs()
+        .find(|(key, _)| *key == "LD_PRELOAD")
+        .map(|(_, value)| value.map(|value| value.to_owned()))
+        .unwrap_or_else(|| std::env::var_os("LD_PRELOAD"));
+
+    let mut value = std::ffi::OsString::from(lib);
+    if let Some(preload) = preload.filter(|preload| !preload.is_empty()) {
+        value.push(":");
+        value.push(preload);
+    }
+
+    command.env("LD_PRELOAD", value);
+}
+
+// Switches a descriptor to non-blocking mode and restores its flags on drop.
+struct NonBlockingGuard {
+    fd: RawFd,
+    flags: OFlag,
+}
+
+impl NonBlockingGuard {
+    fn new(fd: RawFd) -> Result<Self> {
+        let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+        fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+        Ok(Self { fd, flags })
+    }
+}
+
+impl Drop for NonBlockingGuard {
+    fn drop(&mut self) {
+        let _ = fcntl(self.fd, FcntlArg::F_SETFL(self.flags));
+    }
+}
+
+// Restores an echo setting on drop.
+struct EchoRestore {
+    fd: RawFd,
+    on: bool,
+}
+
+impl Drop for EchoRestore {
+    fn drop(&mut self) {
+        let _ = set_echo(self.fd, self.on);
+    }
+}
+
+/// A snapshot of terminal flags returned by [PtyProcess::flags_snapshot].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermiosFlags {
+    /// Input modes, e.g. `IXON`, `ICRNL`.
+    pub input_flags: InputFlags,
+    /// Output modes, e.g. `ONLCR`.
+    pub output_flags: OutputFlags,
+    /// Control modes, e.g. `CS8`.
+    pub control_flags: ControlFlags,
+    /// Local modes, e.g. `ECHO`, `ICANON`, `ISIG`.
+    pub local_flags: LocalFlags,
+}
+
+/// A resource usage of a child process returned by [PtyProcess::resource_usage].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// A resident set size in bytes.
+    pub rss: u64,
+    /// A time spent in user mode.
+    pub user_time: Duration,
+    /// A time spent in kernel mode.
+    pub system_time: Duration,
+}
+
+/// A description of a child exit returned by [PtyProcess::wait_detailed].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExitInfo {
+    /// An exit code if the child exited normally.
+    pub code: Option<i32>,
+    /// A signal which terminated the child.
+    pub signal: Option<Signal>,
+    /// Whether the child produced a core dump.
+    pub core_dumped: bool,
+    /// A raw status, see [PtyProcess::last_status_raw].
+    pub raw: Option<i32>,
+}
+
+/// Returns an exit code if a process exited normally.
+pub fn status_exit_code(status: &WaitStatus) -> Option<i32> {
+    match *status {
+        WaitStatus::Exited(_, code) => Some(code),
+        _ => None,
+    }
+}
+
+/// Returns a signal which terminated a process.
+///
+/// A signal which only stopped a process is not reported.
+pub fn status_signal(status: &WaitStatus) -> Option<Signal> {
+    match *status {
+        WaitStatus::Signaled(_, signal, _) => Some(signal),
+        _ => None,
+    }
+}
+
+/// Returns true if a process was terminated by a signal and produced a core dump.
+pub fn status_core_dumped(status: &WaitStatus) -> bool {
+    matches!(*status, WaitStatus::Signaled(_, _, true))
+}
+
+/// Durations of spawn stages measured in a parent process.
+///
+/// They're reported by `spawn_timed` with the `spawn-timings` feature.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnTimings {
+    /// Opening a pty master (`posix_openpt`).
+    pub open_pty: Duration,
+    /// Granting access to a slave and unlocking it (`grantpt`, `unlockpt`).
+    pub grant_unlock: Duration,
+    /// Applying terminal settings to a master.
+    pub terminal_settings: Duration,
+    /// A `fork` call.
+    pub fork: Duration,
+    /// Waiting for a child to `exec`.
+    ///
+    /// It covers the child's setup, e.g. setting a controlling terminal and closing descriptors.
+    pub exec: Duration,
+}
+
+/// A mode in which a pty delivers input to a child returned by [PtyProcess::read_mode].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadMode {
+    /// Input is delivered line by line (`ICANON` is set).
+    Canonical,
+    /// Input is delivered as soon as it's available (`ICANON` is not set).
+    ///
+    /// A read is completed once `min` bytes are available
+    /// or `time` (in tenths of a second) has passed, see `termios(3)`.
+    NonCanonical {
+        /// A `VMIN` value.
+        min: u8,
+        /// A `VTIME` value.
+        time: u8,
+    },
+}
+
+/// A result of [PtyProcess::poll].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PollResult {
+    /// There's something to read from a pty.
+    pub readable: bool,
+    /// A pty can be written to.
+    pub writable: bool,
+    /// A slave side of a pty was closed.
+    pub hangup: bool,
+    /// A child process has exited.
+    pub exited: bool,
+}
+
+/// PacketFlags describes a control byte of a packet read in packet mode.
+///
+/// See [PtyProcess::read_packet].
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PacketFlags {
+    /// A read queue of a terminal was flushed (`TIOCPKT_FLUSHREAD`).
+    pub flush_read: bool,
+    /// A write queue of a terminal was flushed (`TIOCPKT_FLUSHWRITE`).
+    pub flush_write: bool,
+    /// Output was stopped, e.g. by `^S` (`TIOCPKT_STOP`).
+    pub stop: bool,
+    /// Output was restarted (`TIOCPKT_START`).
+    pub start: bool,
+    /// Start and stop characters are not `^S/^Q` anymore (`TIOCPKT_NOSTOP`).
+    pub no_stop: bool,
+    /// Start and stop characters became `^S/^Q` (`TIOCPKT_DOSTOP`).
+    pub do_stop: bool,
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
+impl PacketFlags {
+    fn from_byte(b: u8) -> Self {
+        Self {
+            flush_read: b & 0x01 != 0,
+            flush_write: b & 0x02 != 0,
+            stop: b & 0x04 != 0,
+            start: b & 0x08 != 0,
+            no_stop: b & 0x10 != 0,
+            do_stop: b & 0x20 != 0,
+        }
+    }
+}
+
+/// A guard returned by [PtyProcess::suppress_kill_on_drop].
+///
+/// While it's alive [PtyProcess] won't kill a child process on drop.
+#[derive(Debug)]
+pub struct KillSuppressionGuard {
+    suppressions: Arc<AtomicUsize>,
+}
+
+impl KillSuppressionGuard {
+    fn new(suppressions: Arc<AtomicUsize>) -> Self {
+        suppressions.fetch_add(1, Ordering::SeqCst);
+        Self { suppressions }
+    }
+}
+
+impl Drop for KillSuppressionGuard {
+    fn drop(&mut self) {
+        self.suppressions.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A future returned by [PtyProcess::exited].
+///
+/// It's available with the `tokio` feature.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct ChildExit<'a> {
+    process: &'a PtyProcess,
+    waiter: ExitWaiter,
+}
+
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+enum ExitWaiter {
+    Pidfd(tokio::io::unix::AsyncFd<OwnedFd>),
+    Thread(Arc<Mutex<ExitWaiterState>>),
+}
+
+#[cfg(feature = "tokio")]
+#[derive(Debug, Default)]
+struct ExitWaiterState {
+    exited: bool,
+    waker: Option<std::task::Waker>,
+}
+
+#[cfg(feature = "tokio")]
+impl std::future::Future for ChildExit<'_> {
+    type Output = Result<WaitStatus>;
+
+    fn poll(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Self::Output> {
+        use std::task::Poll;
+
+        let this = self.get_mut();
+        loop {
+            match this.process.status() {
+                Ok(WaitStatus::StillAlive) => {}
+                result => return Poll::Ready(result),
+            }
+
+            match &mut this.waiter {
+                ExitWaiter::Pidfd(pidfd) => {
+                    let mut guard = match pidfd.poll_read_ready(cx) {
+                        Poll::Ready(guard) => guard.map_err(io_error_to_errno)?,
+                        Poll::Pending => return Poll::Pending,
+                    };
+
+                    // a pidfd is readable once the child exits so the status is checked again,
+                    // readiness is cleared in case it was spurious
+                    guard.clear_ready();
+                }
+                ExitWaiter::Thread(state) => {
+                    let mut state = state.lock().unwrap();
+                    if state.exited {
+                        // the child has exited, so it doesn't block
+                        return Poll::Ready(this.process.wait());
+                    }
+
+                    state.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+// Spawns a thread which waits for a child to exit without reaping it.
+#[cfg(feature = "tokio")]
+fn spawn_exit_waiter(pid: Pid) -> Arc<Mutex<ExitWaiterState>> {
+    let state = Arc::new(Mutex::new(ExitWaiterState::default()));
+
+    let waiter_state = state.clone();
+    thread::spawn(move || {
+        loop {
+            let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+            let flags = libc::WEXITED | libc::WNOWAIT;
+            let res =
+                unsafe { libc::waitid(libc::P_PID, pid.as_raw() as libc::id_t, &mut info, flags) };
+            if errno::Errno::result(res) != Err(Error::EINTR) {
+                break;
+            }
+        }
+
+        let mut state = waiter_state.lock().unwrap();
+        state.exited = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+
+    state
+}
+
+/// A handle to control a child's lifecycle returned by [PtyProcess::handle_and_controller].
+///
+/// The child is terminated on drop in the same way as by [PtyProcess].
+#[derive(Debug)]
+pub struct Controller {
+    process: PtyProcess,
+}
+
+impl Controller {
+    /// See [PtyProcess::kill].
+    pub fn kill(&mut self, signal: signal::Signal) -> Result<()> {
+        self.process.kill(signal)
+    }
+
+    /// See [PtyProcess::wait].
+    pub fn wait(&self) -> Result<WaitStatus> {
+        self.process.wait()
+    }
+
+    /// See [PtyProcess::exit].
+    pub fn exit(&mut self, force: bool) -> Result<bool> {
+        self.process.exit(force)
+    }
+
+    /// See [PtyProcess::status].
+    pub fn status(&self) -> Result<WaitStatus> {
+        self.process.status()
+    }
+}
+
+/// A reading half of a pty returned by [PtyProcess::split].
+#[derive(Debug)]
+pub struct PtyReader {
+    inner: Stream,
+}
+
+impl Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl AsRawFd for PtyReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// A writing half of a pty returned by [PtyProcess::split].
+#[derive(Debug)]
+pub struct PtyWriter {
+    inner: File,
+}
+
+impl Write for PtyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl AsRawFd for PtyWriter {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// An asynchronous reading half of a pty returned by [PtyProcess::split_async].
+///
+/// It's available with the `tokio` feature.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncPtyReader {
+    inner: stream::TokioStream,
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for AsyncPtyReader {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsRawFd for AsyncPtyReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// An asynchronous writing half of a pty returned by [PtyProcess::split_async].
+///
+/// It's available with the `tokio` feature.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncPtyWriter {
+    inner: stream::TokioStream,
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for AsyncPtyWriter {
+    fn poll_write(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        std::pin::Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        std::pin::Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsRawFd for AsyncPtyWriter {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// Target defines where a child's std stream is connected to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// A slave side of a pty.
+    #[default]
+    Pty,
+    /// A given file descriptor.
+    ///
+    /// The descriptor is duplicated in a child so it's left open in a parent.
+    Fd(RawFd),
+    /// `/dev/null`.
+    Null,
+    /// A std stream of a parent process.
+    Inherit,
+}
+
+/// TtyProfile is a set of terminal settings for a common use case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtyProfile {
+    /// A raw mode as made by `cfmakeraw`.
+    ///
+    /// Input is delivered byte by byte and output is not processed.
+    Raw,
+    /// Line by line input with signal generation, without echo.
+    ///
+    /// It's the default settings of a spawned process.
+    Cooked,
+    /// Settings of an interactive shell.
+    ///
+    /// Line by line input with signal generation and echo.
+    Interactive,
+    /// Settings for capturing a clean output.
+    ///
+    /// As [TtyProfile::Cooked] but a newline is not translated into `\r\n`.
+    ScriptFriendly,
+}
+
+/// PtyProcessBuilder configures a child process before it is spawned.
+///
+/// All the settings are applied in the child after a fork and before `exec`.
+///
+/// ```no_run
+/// use ptyprocess::{PtyProcessBuilder, Resource};
+/// use std::process::Command;
+///
+/// let process = PtyProcessBuilder::new()
+///     .rlimit(Resource::RLIMIT_CPU, 10, 10)
+///     .spawn(Command::new("bash"))
+///     .unwrap();
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct PtyProcessBuilder {
+    rlimits: Vec<(Resource, rlim_t, rlim_t)>,
+    nice: Option<(i32, bool)>,
+    stdio: [Target; 3],
+    drop_signal: Option<(Signal, Duration)>,
+    skip_grantpt: bool,
+    separate_stderr_pty: bool,
+    current_dir: Option<PathBuf>,
+    env_keep: Option<Vec<String>>,
+    force_line_buffered: bool,
+    window_size: Option<(u16, u16)>,
+    apply_settings_in_child: bool,
+    echo: bool,
+    raw: bool,
+    terminate_delay: Option<Duration>,
+    profile: Option<TtyProfile>,
+    keep_slave: bool,
+    nonblocking_master: bool,
+}
+
+impl PtyProcessBuilder {
+    /// Creates a builder with a default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a resource limit for a child process via `setrlimit`.
+    ///
+    /// It can be called multiple times for different resources,
+    /// e.g. `RLIMIT_CPU`, `RLIMIT_AS`, `RLIMIT_NOFILE`.
+    ///
+    /// Lowering a limit is always allowed,
+    /// but raising a hard limit requires a permission to do so (e.g. `CAP_SYS_RESOURCE` on Linux).
+    /// If a limit can't be set the error is returned from [Self::spawn].
+    pub fn rlimit(mut self, resource: Resource, soft: rlim_t, hard: rlim_t) -> Self {
+        self.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// Sets a nice value of a child process via `setpriority`.
+    ///
+    /// Lowering a value (raising a priority) requires a permission to do so
+    /// (e.g. `CAP_SYS_NICE` on Linux).
+    /// If `required` is set a failure is returned from [Self::spawn],
+    /// otherwise it's ignored and a child runs with an inherited priority.
+    pub fn nice(mut self, nice: i32, required: bool) -> Self {
+        self.nice = Some((nice, required));
+        self
+    }
+
+    /// Sets where a child's stdin is connected to.
+    ///
+    /// By default it's a pty.
+    pub fn stdin(mut self, target: Target) -> Self {
+        self.stdio[0] = target;
+        self
+    }
+
+    /// Sets where a child's stdout is connected to.
+    ///
+    /// By default it's a pty.
+    pub fn stdout(mut self, target: Target) -> Self {
+        self.stdio[1] = target;
+        self
+    }
+
+    /// Sets where a child's stderr is connected to.
+    ///
+    /// By default it's a pty.
+    pub fn stderr(mut self, target: Target) -> Self {
+        self.stdio[2] = target;
+        self
+    }
+
+    /// Sets a working directory of a child process.
+    ///
+    /// It overrides a directory set by [Command::current_dir].
+    /// If it can't be changed the error is returned from [Self::spawn].
+    pub fn current_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Clears an environment of a child process except the given variables.
+    ///
+    /// It works like `env -i` but keeps the listed variables of a current process.
+    /// Variables set explicitly by [Command::env] are kept as well.
+    pub fn env_clear_except(mut self, keep: &[&str]) -> Self {
+        self.env_keep = Some(keep.iter().map(|&name| name.to_owned()).collect());
+        self
+    }
+
+    /// Makes a child's stdout line buffered even if it's not a terminal.
+    ///
+    /// It's a best-effort convenience which works the same way as `stdbuf -oL`.
+    /// It sets `_STDBUF_O=L` and preloads coreutils' `libstdbuf.so` if it's found,
+    /// so it only affects dynamically linked programs which use C stdio.
+    ///
+    /// It's useful when stdout is redirected by [Self::stdout],
+    /// as a pty makes programs line buffered anyway.
+    pub fn force_line_buffered(mut self, on: bool) -> Self {
+        self.force_line_buffered = on;
+        self
+    }
+
+    /// Sets an initial terminal size.
+    ///
+    /// It's set before `exec` so a child never sees a default 80x24 size.
+    pub fn window_size(mut self, cols: u16, rows: u16) -> Self {
+        self.window_size = Some((cols, rows));
+        self
+    }
+
+    /// Sets an initial echo setting.
+    ///
+    /// By default echo is turned off.
+    pub fn echo(mut self, on: bool) -> Self {
+        self.echo = on;
+        self
+    }
+
+    /// Sets a terminal to a raw mode before a child is started.
+    ///
+    /// An echo setting set by [Self::echo] is applied on top of it.
+    pub fn raw(mut self, on: bool) -> Self {
+        self.raw = on;
+        self
+    }
+
+    /// Configures a terminal by one of predefined profiles.
+    ///
+    /// It overrides settings made by [Self::echo] and [Self::raw].
+    pub fn profile(mut self, profile: TtyProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Keeps a slave side of a pty open in a parent, see [PtyProcess::slave_fd].
+    ///
+    /// Keep in mind that reads won't see EOF until all slave handles are closed,
+    /// including this one.
+    pub fn keep_slave(mut self, on: bool) -> Self {
+        self.keep_slave = on;
+        self
+    }
+
+    /// Opens a master in non-blocking mode (`O_NONBLOCK`) right away.
+    ///
+    /// Every handle of a master shares the mode,
+    /// so reads from [PtyProcess::get_raw_handle] and [PtyProcess::get_pty_stream]
+    /// return [io::ErrorKind::WouldBlock] when there's nothing to read.
+    /// A child is not affected as its std streams are connected to a slave.
+    ///
+    /// Helpers which read until EOF ([PtyProcess::read_all], [PtyProcess::pipe_to]
+    /// and [PtyProcess::finish]) don't wait for an output either,
+    /// so they fail with [io::ErrorKind::WouldBlock] once nothing is buffered.
+    /// [PtyProcess::read_until_prompt], [PtyProcess::read_limited]
+    /// and [PtyProcess::drain_remaining] poll a master first so they work as usual.
+    pub fn nonblocking_master(mut self, on: bool) -> Self {
+        self.nonblocking_master = on;
+        self
+    }
+
+    /// Sets a terminate approach delay, see [PtyProcess::set_terminate_delay].
+    pub fn terminate_delay(mut self, delay: Duration) -> Self {
+        self.terminate_delay = Some(delay);
+        self
+    }
+
+    /// Sets whether initial terminal settings (e.g. echo and a size) are applied by a child.
+    ///
+    /// By default they are applied once via a master in a parent before a fork,
+    /// so the child doesn't receive a `SIGWINCH` for it.
+    /// When it's turned on they are applied via a slave in a child before `exec`.
+    pub fn apply_settings_in_child(mut self, on: bool) -> Self {
+        self.apply_settings_in_child = on;
+        self
+    }
+
+    /// Allocates a separate pty for a child's stderr.
+    ///
+    /// So stderr is still a terminal but it's not interleaved with stdout.
+    /// It can be read by [PtyProcess::stderr_stream].
+    ///
+    /// It overrides a target set by [Self::stderr].
+    pub fn separate_stderr_pty(mut self, on: bool) -> Self {
+        self.separate_stderr_pty = on;
+        self
+    }
+
+    /// Sets a signal which is sent to a child process when [PtyProcess] is dropped.
+    ///
+    /// After the signal is sent it waits at most `timeout` for the child to exit.
+    /// If the child is still alive after that it's left running.
+    ///
+    /// By default [PtyProcess::exit] is used with `force` set,
+    /// which escalates through a number of signals ending with `SIGKILL`.
+    pub fn drop_signal(mut self, signal: Signal, timeout: Duration) -> Self {
+        self.drop_signal = Some((signal, timeout));
+        self
+    }
+
+    /// Skips a `grantpt` call while opening a pty.
+    ///
+    /// On systems where `/dev/pts` is a `devpts` mount `grantpt` is not needed,
+    /// but it may fail in some minimal containers which makes [Self::spawn] fail.
+    pub fn skip_grantpt(mut self, skip: bool) -> Self {
+        self.skip_grantpt = skip;
+        self
+    }
+
+    fn apply_term_settings(&self, fd: RawFd, cols: u16, rows: u16) -> Result<()> {
+        set_term_size(fd, cols, rows)?;
+
+        if let Some(profile) = self.profile {
+            return set_profile(fd, profile);
+        }
+
+        if self.raw {
+            set_raw(fd)?;
+        }
+
+        set_echo(fd, self.echo)
+    }
+
+    fn open_master(&self, timings: &mut SpawnTimings) -> Result<Master> {
+        let flags = match self.nonblocking_master {
+            true => OFlag::O_NONBLOCK,
+            false => OFlag::empty(),
+        };
+
+        let now = time::Instant::now();
+        let master = Master::open_with_flags(flags)?;
+        timings.open_pty += now.elapsed();
+
+        let now = time::Instant::now();
+        if !self.skip_grantpt {
+            master.grant_slave_access()?;
+        }
+        master.unlock_slave()?;
+        timings.grant_unlock += now.elapsed();
+
+        Ok(master)
+    }
+
+    /// Spawns a child process and create a [PtyProcess].
+    ///
+    /// It blocks until a child either calls `exec` or fails to set up,
+    /// so by the time it returns the child has already opened a slave side of a pty.
+    /// Thereby writes made right after a spawn are not lost.
+    ///
+    /// Use [Self::try_spawn] to find out at which stage a spawn has failed.
+    pub fn spawn(&self, command: Command) -> Result<PtyProcess> {
+        self.try_spawn(command).map_err(|err| err.errno())
+    }
+
+    /// Spawns a child process and create a [PtyProcess].
+    ///
+    /// It's the same as [Self::spawn] but an error tells a stage at which it failed.
+    pub fn try_spawn(&self, command: Command) -> std::result::Result<PtyProcess, SpawnError> {
+        self.spawn_with_timings(command).map(|(process, _)| process)
+    }
+
+    /// Spawns a child process and reports how long each stage of a spawn took.
+    ///
+    /// It's available with the `spawn-timings` feature.
+    #[cfg(feature = "spawn-timings")]
+    pub fn spawn_timed(&self, command: Command) -> Result<(PtyProcess, SpawnTimings)> {
+        self.spawn_with_timings(command).map_err(|err| err.errno())
+    }
+
+    fn spawn_with_timings(
+        &self,
+        mut command: Command,
+    ) -> std::result::Result<(PtyProcess, SpawnTimings), SpawnError> {
+        let mut timings = SpawnTimings::default();
+
+        if let Some(keep) = &self.env_keep {
+            clear_env_except(&mut command, keep);
+        }
+
+        if self.force_line_buffered {
+            set_line_buffered_env(&mut command);
+        }
+
+        let (cols, rows) = self
+            .window_size
+            .unwrap_or((DEFAULT_TERM_COLS, DEFAULT_TERM_ROWS));
+
+        let master = self
+            .open_master(&mut timings)
+            .map_err(SpawnError::OpenPty)?;
+        let stderr_master = match self.separate_stderr_pty {
+            true => Some(
+                self.open_master(&mut timings)
+                    .map_err(SpawnError::OpenPty)?,
+            ),
+            false => None,
+        };
+
+        let now = time::Instant::now();
+        if !self.apply_settings_in_child {
+            self.apply_term_settings(master.as_raw_fd(), cols, rows)
+                .map_err(SpawnError::TerminalSettings)?;
+
+            if let Some(stderr_master) = &stderr_master {
+                set_term_size(stderr_master.as_raw_fd(), cols, rows)
+                    .map_err(SpawnError::TerminalSettings)?;
+            }
+        }
+
+        timings.terminal_settings = now.elapsed();
+
+        // We change a directory on our own in a child so its failure is reported via the pipe.
+        // A command's directory is reset so it's not applied twice if it's relative.
+        // It's done before fork as allocating in a child is not safe.
+        let current_dir = self
+            .current_dir
+            .clone()
+            .or_else(|| command.get_current_dir().map(Path::to_path_buf));
+        if current_dir.is_some() {
+            command.current_dir(".");
+        }
+
+        // handle errors in child executions by pipe
+        let (exec_err_pipe_r, exec_err_pipe_w) = pipe().map_err(SpawnError::Fork)?;
+
+        let now = time::Instant::now();
+        let fork = unsafe { fork().map_err(SpawnError::Fork)? };
+        match fork {
+            ForkResult::Child => {
+                let err = || -> std::result::Result<(), SpawnError> {
+                    make_controlling_tty(&master).map_err(SpawnError::ControllingTty)?;
+
+                    let slave_fd = master.get_slave_fd().map_err(SpawnError::RedirectStreams)?;
+
+                    let mut stdio = self.stdio;
+                    if let Some(stderr_master) = &stderr_master {
+                        let stderr_slave_fd = stderr_master
+                            .get_slave_fd()
+                            .map_err(SpawnError::RedirectStreams)?;
+                        if self.apply_settings_in_child {
+                            set_term_size(stderr_slave_fd, cols, rows)
+                                .map_err(SpawnError::TerminalSettings)?;
+                        }
+
+                        stdio[2] = Target::Fd(stderr_slave_fd);
+                    }
+
+                    redirect_std_streams(slave_fd, &stdio).map_err(SpawnError::RedirectStreams)?;
+
+                    if self.apply_settings_in_child {
+                        self.apply_term_settings(slave_fd, cols, rows)
+                            .map_err(SpawnError::TerminalSettings)?;
+                    }
+
+                    if let Some(dir) = current_dir.as_deref() {
+                        unistd::chdir(dir).map_err(SpawnError::CurrentDir)?;
+                    }
+
+                    // Do not allow child to inherit open file descriptors from parent.
+                    // The list is kept on the stack as allocating after fork is not safe.
+                    let mut except = [
+                        0,
+                        1,
+                        2,
+                        slave_fd,
+                        exec_err_pipe_w,
+                        exec_err_pipe_r,
+                        master.as_raw_fd(),
+                        -1,
+                    ];
+                    let mut except_len = except.len() - 1;
+                    if let Some(stderr_master) = &stderr_master {
+                        except[except_len] = stderr_master.as_raw_fd();
+                        except_len += 1;
+                    }
+                    let except = &mut except[..except_len];
+                    except.sort_unstable();
+                    close_all_descriptors(except).map_err(SpawnError::CloseDescriptors)?;
+
+                    close(slave_fd).map_err(SpawnError::CloseDescriptors)?;
+                    close(exec_err_pipe_r).map_err(SpawnError::CloseDescriptors)?;
+                    drop(master);
+                    drop(stderr_master);
+
+                    // Limits are set after descriptors are closed,
+                    // as a lowered RLIMIT_NOFILE is used as a bound for closing.
+                    for &(resource, soft, hard) in &self.rlimits {
+                        setrlimit(resource, soft, hard).map_err(SpawnError::ResourceLimits)?;
+                    }
+
+                    if let Some((nice, required)) = self.nice {
+                        match set_priority(nice) {
+                            Err(err) if required => return Err(SpawnError::Priority(err)),
+                            _ => {}
+                        }
+                    }
+
+                    // close pipe on sucessfull exec
+                    fcntl(exec_err_pipe_w, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+                        .map_err(SpawnError::CloseDescriptors)?;
+
+                    let err = command.exec();
+                    Err(SpawnError::Exec(io_error_to_errno(err)))
+                }()
+                .unwrap_err();
+
+                // Intentionally ignoring errors to exit the process properly
+                let _ = write(exec_err_pipe_w, &err.to_bytes());
+                let _ = close(exec_err_pipe_w);
+
+                process::exit(err.errno() as i32);
+            }
+            ForkResult::Parent { child } => {
+                timings.fork = now.elapsed();
+                let now = time::Instant::now();
+
+                close(exec_err_pipe_w).map_err(SpawnError::Fork)?;
+
+                let mut pipe_buf = [0u8; 8];
+                unistd::read(exec_err_pipe_r, &mut pipe_buf).map_err(SpawnError::Fork)?;
+                close(exec_err_pipe_r).map_err(SpawnError::Fork)?;
+                timings.exec = now.elapsed();
+
+                if let Some(err) = SpawnError::from_bytes(pipe_buf) {
+                    // reap a child as it exits right after reporting the error
+                    let _ = wait::waitpid(child, None);
+                    return Err(err);
+                }
+
+                let slave = match self.keep_slave {
+                    true => Some(open_parent_slave(&master).map_err(SpawnError::RedirectStreams)?),
+                    false => None,
+                };
+
+                let mut process =
+                    PtyProcess::new(master, slave, child).map_err(SpawnError::TerminalSettings)?;
+                process.drop_signal = self.drop_signal;
+                process.terminate_delay = self.terminate_delay.unwrap_or(DEFAULT_TERMINATE_DELAY);
+                process.stderr_master = stderr_master;
+
+                Ok((process, timings))
+            }
+        }
+    }
+}
+
+/// SpawnError tells at which stage a spawn of a child process has failed.
+///
+/// Each variant holds an errno of the failed call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// Opening a pty failed.
+    OpenPty(Error),
+    /// Creating a pipe or forking failed.
+    Fork(Error),
+    /// Making a pty a controlling terminal of a child failed.
+    ControllingTty(Error),
+    /// Connecting std streams of a child failed.
+    RedirectStreams(Error),
+    /// Setting terminal settings failed.
+    TerminalSettings(Error),
+    /// Setting resource limits failed.
+    ResourceLimits(Error),
+    /// Changing a working directory failed.
+    CurrentDir(Error),
+    /// Setting a scheduling priority failed.
+    Priority(Error),
+    /// Closing inherited file descriptors failed.
+    CloseDescriptors(Error),
+    /// `exec` failed.
+    Exec(Error),
+}
 
-    /// Get a interapt character if set or a default.
-    pub fn get_intr_char(&self) -> u8 {
-        self.intr_char
+impl SpawnError {
+    /// Returns an errno of the failed call.
+    pub fn errno(&self) -> Error {
+        match *self {
+            Self::OpenPty(err)
+            | Self::Fork(err)
+            | Self::ControllingTty(err)
+            | Self::RedirectStreams(err)
+            | Self::TerminalSettings(err)
+            | Self::ResourceLimits(err)
+            | Self::CurrentDir(err)
+            | Self::Priority(err)
+            | Self::CloseDescriptors(err)
+            | Self::Exec(err) => err,
+        }
     }
 
-    /// Get window size of a terminal.
-    ///
-    /// Default size is 80x24.
-    pub fn get_window_size(&self) -> Result<(u16, u16)> {
-        get_term_size(self.master.as_raw_fd())
-    }
+    // A stage tag followed by an errno which is sent by a child via a pipe.
+    fn to_bytes(self) -> [u8; 8] {
+        let tag: u32 = match self {
+            Self::OpenPty(_) => 1,
+            Self::Fork(_) => 2,
+            Self::ControllingTty(_) => 3,
+            Self::RedirectStreams(_) => 4,
+            Self::TerminalSettings(_) => 5,
+            Self::ResourceLimits(_) => 6,
+            Self::CurrentDir(_) => 7,
+            Self::CloseDescriptors(_) => 8,
+            Self::Exec(_) => 9,
+            Self::Priority(_) => 10,
+        };
 
-    /// Sets a terminal size.
-    pub fn set_window_size(&mut self, cols: u16, rows: u16) -> Result<()> {
-        set_term_size(self.master.as_raw_fd(), cols, rows)
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&tag.to_be_bytes());
+        bytes[4..].copy_from_slice(&(self.errno() as i32).to_be_bytes());
+        bytes
     }
 
-    /// The function returns true if an echo setting is setup.
-    pub fn get_echo(&self) -> Result<bool> {
-        termios::tcgetattr(self.master.as_raw_fd())
-            .map(|flags| flags.local_flags.contains(termios::LocalFlags::ECHO))
-    }
+    // Zero bytes are read when a pipe was closed by a successful exec.
+    fn from_bytes(bytes: [u8; 8]) -> Option<Self> {
+        let tag = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let errno = errno::from_i32(i32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]));
+
+        let err = match tag {
+            0 => return None,
+            1 => Self::OpenPty(errno),
+            2 => Self::Fork(errno),
+            3 => Self::ControllingTty(errno),
+            4 => Self::RedirectStreams(errno),
+            5 => Self::TerminalSettings(errno),
+            6 => Self::ResourceLimits(errno),
+            7 => Self::CurrentDir(errno),
+            8 => Self::CloseDescriptors(errno),
+            10 => Self::Priority(errno),
+            _ => Self::Exec(errno),
+        };
 
-    /// Sets a echo setting for a terminal
-    pub fn set_echo(&mut self, on: bool, timeout: Option<Duration>) -> Result<bool> {
-        set_echo(self.master.as_raw_fd(), on)?;
-        self.wait_echo(on, timeout)
+        Some(err)
     }
+}
 
-    /// Returns true if a underline `fd` connected with a TTY.
-    pub fn isatty(&self) -> Result<bool> {
-        isatty(self.master.as_raw_fd())
-    }
+impl std::fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stage = match self {
+            Self::OpenPty(_) => "failed to open a pty",
+            Self::Fork(_) => "failed to fork",
+            Self::ControllingTty(_) => "failed to set a controlling terminal",
+            Self::RedirectStreams(_) => "failed to redirect std streams",
+            Self::TerminalSettings(_) => "failed to set terminal settings",
+            Self::ResourceLimits(_) => "failed to set resource limits",
+            Self::CurrentDir(_) => "failed to change a working directory",
+            Self::Priority(_) => "failed to set a priority",
+            Self::CloseDescriptors(_) => "failed to close file descriptors",
+            Self::Exec(_) => "failed to exec",
+        };
 
-    /// Set the pty process's terminate approach delay.
-    pub fn set_terminate_delay(&mut self, terminate_approach_delay: Duration) {
-        self.terminate_delay = terminate_approach_delay;
+        write!(f, "{}: {}", stage, self.errno())
     }
+}
 
-    /// Status returns a status a of child process.
-    pub fn status(&self) -> Result<WaitStatus> {
-        waitpid(self.child_pid, Some(wait::WaitPidFlag::WNOHANG))
-    }
+impl std::error::Error for SpawnError {}
 
-    /// Kill sends a signal to a child process.
-    ///
-    /// The operation is non-blocking.
-    pub fn kill(&mut self, signal: signal::Signal) -> Result<()> {
-        signal::kill(self.child_pid, signal)
+impl From<SpawnError> for Error {
+    fn from(err: SpawnError) -> Self {
+        err.errno()
     }
+}
 
-    /// Signal is an alias to [PtyProcess::kill].
-    ///
-    /// [PtyProcess::kill]: struct.PtyProcess.html#method.kill
-    pub fn signal(&mut self, signal: signal::Signal) -> Result<()> {
-        self.kill(signal)
-    }
+fn set_cloexec(fd: RawFd, on: bool) -> Result<()> {
+    let flags = match on {
+        true => FdFlag::FD_CLOEXEC,
+        false => FdFlag::empty(),
+    };
 
-    /// Wait blocks until a child process exits.
-    ///
-    /// It returns a error if the child was DEAD or not exist
-    /// at the time of a call.
-    ///
-    /// If you need to verify that a process is dead in non-blocking way you can use
-    /// [is_alive] method.
-    ///
-    /// [is_alive]: struct.PtyProcess.html#method.is_alive
-    pub fn wait(&self) -> Result<WaitStatus> {
-        waitpid(self.child_pid, None)
-    }
+    fcntl(fd, FcntlArg::F_SETFD(flags)).map(drop)
+}
 
-    /// Checks if a process is still exists.
-    ///
-    /// It's a non blocking operation.
-    ///
-    /// Keep in mind that after calling this method process might be marked as DEAD by kernel,
-    /// because a check of its status.
-    /// Therefore second call to [Self::status] or [Self::is_alive] might return a different status.
-    pub fn is_alive(&self) -> Result<bool> {
-        let status = self.status();
-        match status {
-            Ok(status) if status == WaitStatus::StillAlive => Ok(true),
-            Ok(_) | Err(Error::ECHILD) | Err(Error::ESRCH) => Ok(false),
-            Err(err) => Err(err),
-        }
-    }
+// Opens a slave which is not inherited by other spawned processes.
+fn open_parent_slave(master: &Master) -> Result<OwnedFd> {
+    // O_CLOEXEC is set atomically, so a child spawned by another thread meanwhile doesn't get it.
+    let fd = master.open_slave(OFlag::O_CLOEXEC)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
 
-    /// Try to force a child to terminate.
-    ///
-    /// This returns true if the child was terminated. and returns false if the
-    /// child could not be terminated.
-    ///
-    /// It makes 4 tries getting more thorough.
-    ///
-    /// 1. SIGHUP
-    /// 2. SIGCONT
-    /// 3. SIGINT
-    /// 4. SIGTERM
-    ///
-    /// If "force" is `true` then moves onto SIGKILL.
-    pub fn exit(&mut self, force: bool) -> Result<bool> {
-        if !self.is_alive()? {
-            return Ok(true);
-        }
+fn set_priority(nice: i32) -> Result<()> {
+    let res = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) };
+    errno::Errno::result(res).map(drop)
+}
 
-        for &signal in &[
-            signal::SIGHUP,
-            signal::SIGCONT,
-            signal::SIGINT,
-            signal::SIGTERM,
-        ] {
-            if self.try_to_terminate(signal)? {
-                return Ok(true);
-            }
-        }
+// Polls a descriptor for reading, a poll interrupted by a signal is restarted.
+// Returns how many bytes at least are needed for `output` to end with `prompt`,
+// it's the prompt length minus the longest tail of `output` which starts the prompt.
+// Reading this many bytes can't go past the end of the prompt.
+fn prompt_missing_len(output: &[u8], prompt: &[u8]) -> usize {
+    let matched = (1..prompt.len())
+        .rev()
+        .find(|&n| output.ends_with(&prompt[..n]))
+        .unwrap_or(0);
+
+    prompt.len() - matched
+}
 
-        if !force {
-            return Ok(false);
+fn poll_readable(fd: RawFd, timeout: Duration) -> io::Result<PollFlags> {
+    let now = time::Instant::now();
+    loop {
+        let left = timeout.saturating_sub(now.elapsed());
+        let left = left.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match poll(&mut fds, left) {
+            Ok(_) => return Ok(fds[0].revents().unwrap_or_else(PollFlags::empty)),
+            Err(Error::EINTR) => continue,
+            Err(err) => return Err(err.into()),
         }
-
-        self.try_to_terminate(SIGKILL)
     }
+}
 
-    fn try_to_terminate(&mut self, signal: signal::Signal) -> Result<bool> {
-        self.kill(signal)?;
-        thread::sleep(self.terminate_delay);
+// The same as [nix::sys::wait::waitpid] but it also returns a raw status if any.
+fn waitpid_raw(pid: Pid, options: Option<wait::WaitPidFlag>) -> Result<(WaitStatus, Option<i32>)> {
+    let mut status = 0;
+    let options = options.map_or(0, |options| options.bits());
 
-        self.is_alive().map(|is_alive| !is_alive)
+    let res = unsafe { libc::waitpid(pid.as_raw(), &mut status, options) };
+    match errno::Errno::result(res)? {
+        0 => Ok((WaitStatus::StillAlive, None)),
+        res => WaitStatus::from_raw(Pid::from_raw(res), status).map(|s| (s, Some(status))),
     }
+}
 
-    fn wait_echo(&self, on: bool, timeout: Option<Duration>) -> Result<bool> {
-        let now = time::Instant::now();
-        while timeout.is_none() || now.elapsed() < timeout.unwrap() {
-            if on == self.get_echo()? {
-                return Ok(true);
-            }
+#[cfg(target_os = "linux")]
+fn open_pidfd(pid: Pid) -> Option<OwnedFd> {
+    // pidfd_open is available since Linux 5.3 so we ignore errors like ENOSYS.
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    match fd {
+        -1 => None,
+        fd => Some(unsafe { OwnedFd::from_raw_fd(fd as RawFd) }),
+    }
+}
 
-            thread::sleep(Duration::from_millis(100));
-        }
+#[cfg(not(target_os = "linux"))]
+fn open_pidfd(_: Pid) -> Option<OwnedFd> {
+    None
+}
 
-        Ok(false)
-    }
+#[cfg(target_os = "linux")]
+fn pidfd_send_signal(pidfd: &OwnedFd, signal: Signal) -> Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.as_raw_fd(),
+            signal as libc::c_int,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    errno::Errno::result(res).map(drop)
 }
 
-impl Drop for PtyProcess {
-    fn drop(&mut self) {
-        if let Ok(WaitStatus::StillAlive) = self.status() {
-            self.exit(true).unwrap();
-        }
-    }
+#[cfg(not(target_os = "linux"))]
+fn pidfd_send_signal(_: &OwnedFd, _: Signal) -> Result<()> {
+    Err(Error::ENOSYS)
+}
+
+// A pidfd becomes readable once a process exits.
+fn pidfd_has_exited(pidfd: &OwnedFd) -> Result<bool> {
+    let mut fds = [PollFd::new(pidfd.as_raw_fd(), PollFlags::POLLIN)];
+    poll(&mut fds, 0)?;
+
+    let revents = fds[0].revents().unwrap_or_else(PollFlags::empty);
+    Ok(revents.contains(PollFlags::POLLIN))
+}
+
+// Reads a field of `/proc/<pid>/stat` by its number as it's documented in `proc(5)`.
+#[cfg(target_os = "linux")]
+fn read_proc_stat_field<T: std::str::FromStr>(pid: Pid, field: usize) -> Result<T> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).map_err(io_error_to_errno)?;
+
+    // A command name (the 2nd field) may contain spaces so we skip it.
+    stat.rsplit_once(')')
+        .and_then(|(_, fields)| fields.split_whitespace().nth(field - 3))
+        .and_then(|value| value.parse().ok())
+        .ok_or(Error::EINVAL)
+}
+
+fn io_error_to_errno(err: io::Error) -> Error {
+    err.raw_os_error()
+        .map_or(errno::Errno::EIO, errno::from_i32)
 }
 
 fn set_term_size(fd: i32, cols: u16, rows: u16) -> Result<()> {
@@ -410,51 +2998,96 @@ fn get_term_size(fd: i32) -> Result<(u16, u16)> {
 
 #[derive(Debug)]
 struct Master {
-    fd: PtyMaster,
+    fd: OwnedFd,
 }
 
 impl Master {
+    #[cfg(test)]
     fn open() -> Result<Self> {
-        let master_fd = posix_openpt(OFlag::O_RDWR)?;
-        Ok(Self { fd: master_fd })
+        Self::open_with_flags(OFlag::empty())
+    }
+
+    fn open_with_flags(flags: OFlag) -> Result<Self> {
+        // On Linux O_CLOEXEC is passed to open("/dev/ptmx") as is,
+        // so a master is never leaked into a child spawned by another thread meanwhile.
+        #[cfg(target_os = "linux")]
+        let flags = flags | OFlag::O_CLOEXEC;
+
+        let fd = unsafe { libc::posix_openpt((OFlag::O_RDWR | flags).bits()) };
+        let fd = errno::Errno::result(fd)?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // O_CLOEXEC is not accepted by posix_openpt on other platforms,
+        // so it's set separately to not leak a master into other spawned children.
+        #[cfg(not(target_os = "linux"))]
+        set_cloexec(fd.as_raw_fd(), true)?;
+
+        Ok(Self { fd })
+    }
+
+    fn from_fd(fd: OwnedFd) -> Result<Self> {
+        set_cloexec(fd.as_raw_fd(), true)?;
+        let master = Self { fd };
+
+        // makes sure it's a pty master
+        master.get_slave_name()?;
+
+        Ok(master)
     }
 
     fn grant_slave_access(&self) -> Result<()> {
-        grantpt(&self.fd)
+        let res = unsafe { libc::grantpt(self.as_raw_fd()) };
+        match errno::Errno::result(res).map(drop) {
+            // devpts sets a slave ownership and permissions on its own,
+            // so a failure of grantpt in such case is benign.
+            Err(_) if cfg!(target_os = "linux") && self.is_devpts_slave() => Ok(()),
+            result => result,
+        }
+    }
+
+    fn is_devpts_slave(&self) -> bool {
+        self.get_slave_name()
+            .map(|name| name.starts_with("/dev/pts/"))
+            .unwrap_or(false)
     }
 
     fn unlock_slave(&self) -> Result<()> {
-        unlockpt(&self.fd)
+        let res = unsafe { libc::unlockpt(self.as_raw_fd()) };
+        errno::Errno::result(res).map(drop)
     }
 
     fn get_slave_name(&self) -> Result<String> {
-        get_slave_name(&self.fd)
+        get_slave_name(self.as_raw_fd())
     }
 
-    #[cfg(not(target_os = "freebsd"))]
     fn get_slave_fd(&self) -> Result<RawFd> {
+        self.open_slave(OFlag::empty())
+    }
+
+    #[cfg(not(target_os = "freebsd"))]
+    fn open_slave(&self, flags: OFlag) -> Result<RawFd> {
         let slave_name = self.get_slave_name()?;
         let slave_fd = open(
             slave_name.as_str(),
-            OFlag::O_RDWR | OFlag::O_NOCTTY,
+            OFlag::O_RDWR | OFlag::O_NOCTTY | flags,
             Mode::empty(),
         )?;
         Ok(slave_fd)
     }
 
     #[cfg(target_os = "freebsd")]
-    fn get_slave_fd(&self) -> Result<RawFd> {
+    fn open_slave(&self, flags: OFlag) -> Result<RawFd> {
         let slave_name = self.get_slave_name()?;
         let slave_fd = open(
             format!("/dev/{}", slave_name.as_str()).as_str(),
-            OFlag::O_RDWR | OFlag::O_NOCTTY,
+            OFlag::O_RDWR | OFlag::O_NOCTTY | flags,
             Mode::empty(),
         )?;
         Ok(slave_fd)
     }
 
     fn get_file_handle(&self) -> Result<File> {
-        let fd = dup(self.as_raw_fd())?;
+        let fd = fcntl(self.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(0))?;
         let file = unsafe { File::from_raw_fd(fd) };
 
         Ok(file)
@@ -467,18 +3100,35 @@ impl AsRawFd for Master {
     }
 }
 
+impl AsFd for Master {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
 #[cfg(target_os = "linux")]
-fn get_slave_name(fd: &PtyMaster) -> Result<String> {
-    nix::pty::ptsname_r(fd)
+fn get_slave_name(fd: RawFd) -> Result<String> {
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+
+    let mut buf: [c_char; 64] = [0; 64];
+
+    // ptsname_r returns an error number instead of setting errno
+    match unsafe { libc::ptsname_r(fd, buf.as_mut_ptr(), buf.len()) } {
+        0 => {
+            let string = unsafe { CStr::from_ptr(buf.as_ptr()) }
+                .to_string_lossy()
+                .into_owned();
+            Ok(string)
+        }
+        errno => Err(Error::from_i32(errno)),
+    }
 }
 
 #[cfg(target_os = "freebsd")]
-fn get_slave_name(fd: &PtyMaster) -> Result<String> {
+fn get_slave_name(fd: RawFd) -> Result<String> {
     use std::ffi::CStr;
     use std::os::raw::c_char;
-    use std::os::unix::prelude::AsRawFd;
-
-    let fd = fd.as_raw_fd();
 
     if !isptmaster(fd)? {
         // never reached according current implementation of isptmaster
@@ -540,12 +3190,11 @@ fn fdevname_r(fd: RawFd, buf: &mut [std::os::raw::c_char]) -> Result<()> {
 /// Getting a slave name on darvin platform
 /// https://blog.tarq.io/ptsname-on-osx-with-rust/
 #[cfg(target_os = "macos")]
-fn get_slave_name(fd: &PtyMaster) -> Result<String> {
+fn get_slave_name(fd: RawFd) -> Result<String> {
     use nix::libc::ioctl;
     use nix::libc::TIOCPTYGNAME;
     use std::ffi::CStr;
     use std::os::raw::c_char;
-    use std::os::unix::prelude::AsRawFd;
 
     // ptsname_r is a linux extension but ptsname isn't thread-safe
     // we could use a static mutex but instead we re-implemented ptsname_r with a syscall
@@ -553,8 +3202,6 @@ fn get_slave_name(fd: &PtyMaster) -> Result<String> {
     // the buffer size on OSX is 128, defined by sys/ttycom.h
     let mut buf: [c_char; 128] = [0; 128];
 
-    let fd = fd.as_raw_fd();
-
     match unsafe { ioctl(fd, TIOCPTYGNAME as u64, &mut buf) } {
         0 => {
             let string = unsafe { CStr::from_ptr(buf.as_ptr()) }
@@ -566,17 +3213,28 @@ fn get_slave_name(fd: &PtyMaster) -> Result<String> {
     }
 }
 
-fn redirect_std_streams(fd: RawFd) -> Result<()> {
-    // If fildes2 is already a valid open file descriptor, it shall be closed first
-
-    close(STDIN_FILENO)?;
-    close(STDOUT_FILENO)?;
-    close(STDERR_FILENO)?;
+fn redirect_std_streams(fd: RawFd, targets: &[Target; 3]) -> Result<()> {
+    // Resolve all sources before any std stream is replaced,
+    // because a target may refer to one of them, e.g. `Target::Fd(STDOUT_FILENO)`.
+    //
+    // The duplicated descriptors are closed later on by `close_all_descriptors`.
+    let mut sources = [None; 3];
+    for (source, target) in sources.iter_mut().zip(targets) {
+        *source = match *target {
+            Target::Pty => Some(fd),
+            Target::Fd(fd) => Some(dup(fd)?),
+            Target::Null => Some(open("/dev/null", OFlag::O_RDWR, Mode::empty())?),
+            Target::Inherit => None,
+        };
+    }
 
-    // use slave fd as std[in/out/err]
-    dup2(fd, STDIN_FILENO)?;
-    dup2(fd, STDOUT_FILENO)?;
-    dup2(fd, STDERR_FILENO)?;
+    // If fildes2 is already a valid open file descriptor, it shall be closed first
+    let std_fds = [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO];
+    for (source, std_fd) in sources.iter().zip(std_fds) {
+        if let Some(source) = *source {
+            dup2(source, std_fd)?;
+        }
+    }
 
     Ok(())
 }
@@ -594,6 +3252,34 @@ fn set_echo(fd: RawFd, on: bool) -> Result<()> {
     Ok(())
 }
 
+fn set_profile(fd: RawFd, profile: TtyProfile) -> Result<()> {
+    if profile == TtyProfile::Raw {
+        return set_raw(fd);
+    }
+
+    let mut flags = termios::tcgetattr(fd)?;
+    flags.local_flags |= LocalFlags::ICANON | LocalFlags::ISIG;
+    flags.output_flags |= OutputFlags::OPOST | OutputFlags::ONLCR;
+    flags.control_chars[termios::SpecialCharacterIndices::VMIN as usize] = 1;
+    flags.control_chars[termios::SpecialCharacterIndices::VTIME as usize] = 0;
+
+    match profile {
+        TtyProfile::Raw => unreachable!(),
+        TtyProfile::Cooked => {
+            flags.local_flags &= !LocalFlags::ECHO;
+        }
+        TtyProfile::Interactive => {
+            flags.local_flags |= LocalFlags::ECHO | LocalFlags::ECHOE | LocalFlags::ECHOK;
+        }
+        TtyProfile::ScriptFriendly => {
+            flags.local_flags &= !LocalFlags::ECHO;
+            flags.output_flags &= !OutputFlags::ONLCR;
+        }
+    }
+
+    termios::tcsetattr(fd, termios::SetArg::TCSANOW, &flags)
+}
+
 pub fn set_raw(fd: RawFd) -> Result<()> {
     let mut flags = termios::tcgetattr(fd)?;
 
@@ -628,22 +3314,14 @@ pub fn set_raw(fd: RawFd) -> Result<()> {
     Ok(())
 }
 
-fn get_this_term_char(char: SpecialCharacterIndices) -> Option<u8> {
-    for &fd in &[STDIN_FILENO, STDOUT_FILENO] {
-        if let Ok(char) = get_term_char(fd, char) {
-            return Some(char);
-        }
-    }
-
-    None
-}
-
-fn get_intr_char() -> u8 {
-    get_this_term_char(SpecialCharacterIndices::VINTR).unwrap_or(DEFAULT_INTR_CHAR)
+// Control characters are read from a pty itself,
+// as they're what a child's line discipline uses.
+fn get_intr_char(fd: RawFd) -> u8 {
+    get_term_char(fd, SpecialCharacterIndices::VINTR).unwrap_or(DEFAULT_INTR_CHAR)
 }
 
-fn get_eof_char() -> u8 {
-    get_this_term_char(SpecialCharacterIndices::VEOF).unwrap_or(DEFAULT_VEOF_CHAR)
+fn get_eof_char(fd: RawFd) -> u8 {
+    get_term_char(fd, SpecialCharacterIndices::VEOF).unwrap_or(DEFAULT_VEOF_CHAR)
 }
 
 fn get_term_char(fd: RawFd, char: SpecialCharacterIndices) -> Result<u8> {
@@ -652,6 +3330,12 @@ fn get_term_char(fd: RawFd, char: SpecialCharacterIndices) -> Result<u8> {
     Ok(b)
 }
 
+fn set_term_char(fd: RawFd, char: SpecialCharacterIndices, b: u8) -> Result<()> {
+    let mut flags = termios::tcgetattr(fd)?;
+    flags.control_chars[char as usize] = b;
+    termios::tcsetattr(fd, termios::SetArg::TCSANOW, &flags)
+}
+
 fn make_controlling_tty(ptm: &Master) -> Result<()> {
     #[cfg(not(any(target_os = "freebsd", target_os = "macos")))]
     {
@@ -718,10 +3402,26 @@ fn make_controlling_tty(ptm: &Master) -> Result<()> {
     Ok(())
 }
 
+// Closes all descriptors but the ones in `except`, the list must be sorted.
+//
 // Except is used for cases like double free memory
 fn close_all_descriptors(except: &[RawFd]) -> Result<()> {
-    // On linux could be used getrlimit(RLIMIT_NOFILE, rlim) interface
-    let max_open_fds = sysconf(SysconfVar::OPEN_MAX)?.unwrap() as i32;
+    debug_assert!(except.windows(2).all(|w| w[0] <= w[1]));
+
+    #[cfg(target_os = "linux")]
+    {
+        if close_ranges(except) || close_listed_descriptors(except) {
+            return Ok(());
+        }
+    }
+
+    // No descriptor above a soft limit can be opened.
+    let open_max = sysconf(SysconfVar::OPEN_MAX)?.unwrap() as rlim_t;
+    let max_open_fds = match getrlimit(Resource::RLIMIT_NOFILE) {
+        Ok((soft, _)) => soft.min(open_max),
+        Err(_) => open_max,
+    };
+    let max_open_fds = max_open_fds.min(RawFd::MAX as rlim_t) as RawFd;
     (0..max_open_fds)
         .filter(|fd| !except.contains(fd))
         .for_each(|fd| {
@@ -733,10 +3433,114 @@ fn close_all_descriptors(except: &[RawFd]) -> Result<()> {
     Ok(())
 }
 
+// Closes open descriptors listed in `/proc/self/fd` but the ones in `except`.
+//
+// It runs in a child after fork where allocation is not async-signal-safe,
+// so the directory is read by raw syscalls into a buffer on the stack.
+//
+// Returns `false` if the list is not available.
+#[cfg(target_os = "linux")]
+fn close_listed_descriptors(except: &[RawFd]) -> bool {
+    // struct linux_dirent64 { u64 d_ino; s64 d_off; u16 d_reclen; u8 d_type; char d_name[]; }
+    const RECLEN_OFFSET: usize = 16;
+    const NAME_OFFSET: usize = 19;
+
+    let path = b"/proc/self/fd\0";
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+    let dir = unsafe { libc::open(path.as_ptr().cast(), flags) };
+    if dir < 0 {
+        return false;
+    }
+
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = unsafe { libc::syscall(libc::SYS_getdents64, dir, buf.as_mut_ptr(), buf.len()) };
+        if n <= 0 {
+            break;
+        }
+
+        let mut offset = 0;
+        while offset < n as usize {
+            let reclen = &buf[offset + RECLEN_OFFSET..offset + RECLEN_OFFSET + 2];
+            let reclen = u16::from_ne_bytes([reclen[0], reclen[1]]) as usize;
+            let name = &buf[offset + NAME_OFFSET..offset + reclen];
+
+            // "." and ".." are skipped as not numbers
+            if let Some(fd) = parse_descriptor(name) {
+                if fd != dir && except.binary_search(&fd).is_err() {
+                    let _ = close(fd);
+                }
+            }
+
+            offset += reclen;
+        }
+    }
+
+    let _ = close(dir);
+
+    true
+}
+
+// Parses a NUL terminated descriptor number without allocation.
+#[cfg(target_os = "linux")]
+fn parse_descriptor(name: &[u8]) -> Option<RawFd> {
+    let name = name.split(|&b| b == 0).next()?;
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut fd: RawFd = 0;
+    for &b in name {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+
+        fd = fd.checked_mul(10)?.checked_add(RawFd::from(b - b'0'))?;
+    }
+
+    Some(fd)
+}
+
+// Closes the gaps between `except` descriptors by `close_range(2)`.
+//
+// Returns `false` if the syscall is not available,
+// in which case some of the descriptors may be closed already.
+#[cfg(target_os = "linux")]
+fn close_ranges(except: &[RawFd]) -> bool {
+    let mut first: libc::c_uint = 0;
+    for &fd in except.iter().filter(|&&fd| fd >= 0) {
+        let fd = fd as libc::c_uint;
+        if fd > first && !close_range(first, fd - 1) {
+            return false;
+        }
+
+        first = fd + 1;
+    }
+
+    close_range(first, libc::c_uint::MAX)
+}
+
+#[cfg(target_os = "linux")]
+fn close_range(first: libc::c_uint, last: libc::c_uint) -> bool {
+    // Not only ENOSYS means it's unsupported,
+    // e.g. seccomp profiles of some container runtimes return EPERM for unknown syscalls.
+    let ret = unsafe { libc::syscall(libc::SYS_close_range, first, last, 0) };
+    ret == 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn prompt_missing_len() {
+        assert_eq!(super::prompt_missing_len(b"", b"$ "), 2);
+        assert_eq!(super::prompt_missing_len(b"abc$", b"$ "), 1);
+        assert_eq!(super::prompt_missing_len(b"ab", b"abab"), 2);
+        assert_eq!(super::prompt_missing_len(b"aba", b"abab"), 1);
+        assert_eq!(super::prompt_missing_len(b"abc", b"abab"), 4);
+    }
+
     #[test]
     fn create_pty() -> Result<()> {
         let master = Master::open()?;
@@ -773,4 +3577,33 @@ mod tests {
 
         Ok(())
     }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn force_line_buffered() {
+        // it's checked the same way as the builder looks the library up
+        if libstdbuf_path().is_none() {
+            eprintln!("force_line_buffered is skipped as libstdbuf.so is not found");
+            return;
+        }
+
+        let (r, w) = pipe().unwrap();
+
+        let mut cmd = Command::new("tr");
+        cmd.args(["a", "b"]);
+        let mut proc = PtyProcessBuilder::new()
+            .stdout(Target::Fd(w))
+            .force_line_buffered(true)
+            .spawn(cmd)
+            .unwrap();
+        close(w).unwrap();
+
+        proc.send_now(b"aaa\n").unwrap();
+
+        let mut buf = [0; 128];
+        let n = unistd::read(r, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"bbb\n");
+
+        close(r).unwrap();
+    }
 }
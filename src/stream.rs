@@ -2,21 +2,112 @@
 use std::{
     fs::File,
     io::{self, Read, Write},
-    os::unix::{io::AsRawFd, prelude::RawFd},
+    os::unix::{
+        io::{AsFd, AsRawFd, BorrowedFd, OwnedFd},
+        prelude::RawFd,
+    },
+    time::Duration,
+};
+
+use nix::poll::PollFlags;
+
+#[cfg(feature = "tokio")]
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+#[cfg(feature = "tokio")]
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
 };
 
 /// Stream represent a duplex pipe.
 ///
 /// It must work in the same way on all platforms.
+///
+/// It doesn't do any buffering so writes go directly to a pty.
 #[derive(Debug)]
 pub struct Stream {
     inner: File,
+    eio_as_eof: bool,
+    read_chunk_size: usize,
 }
 
+const DEFAULT_READ_CHUNK_SIZE: usize = 8 * 1024;
+
 impl Stream {
     /// The function returns a new Stream from a file.
     pub fn new(file: File) -> Self {
-        Self { inner: file }
+        Self {
+            inner: file,
+            eio_as_eof: true,
+            read_chunk_size: DEFAULT_READ_CHUNK_SIZE,
+        }
+    }
+
+    /// Creates a new independent handle to the same pty.
+    ///
+    /// A descriptor is duplicated, so a blocking mode is shared
+    /// as it's a property of an open file description.
+    /// Stream settings are copied.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            inner: self.inner.try_clone()?,
+            eio_as_eof: self.eio_as_eof,
+            read_chunk_size: self.read_chunk_size,
+        })
+    }
+
+    /// Sets a size of reads made by [Self::read_all].
+    ///
+    /// Default is 8 KiB.
+    pub fn set_read_chunk_size(&mut self, n: usize) {
+        self.read_chunk_size = n.max(1);
+    }
+
+    /// Reads everything until EOF.
+    ///
+    /// It reads by chunks set by [Self::set_read_chunk_size].
+    pub fn read_all(&mut self) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        let mut buf = vec![0; self.read_chunk_size];
+        loop {
+            match self.read(&mut buf) {
+                Ok(0) => return Ok(output),
+                Ok(n) => output.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Reads into `buf` waiting for data not longer than `timeout`.
+    ///
+    /// It returns [None] if nothing became available in time.
+    /// Waiting is done by `poll`, so unlike a non-blocking mode
+    /// it doesn't affect other handles of the same descriptor.
+    pub fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        let revents = crate::poll_readable(self.as_raw_fd(), timeout)?;
+        if !revents.intersects(PollFlags::POLLIN | PollFlags::POLLHUP) {
+            return Ok(None);
+        }
+
+        self.read(buf).map(Some)
+    }
+
+    /// Sets or clears close-on-exec flag (`FD_CLOEXEC`) of a descriptor.
+    ///
+    /// It's set by default.
+    pub fn set_cloexec(&mut self, on: bool) -> io::Result<()> {
+        crate::set_cloexec(self.as_raw_fd(), on)?;
+        Ok(())
+    }
+
+    /// Sets whether `EIO` error is treated as EOF on read.
+    ///
+    /// By default it's turned on, as `EIO` is returned on some platforms
+    /// once a child has closed a slave side of a pty.
+    /// When it's turned off the error is returned as it is.
+    pub fn set_eio_as_eof(&mut self, on: bool) {
+        self.eio_as_eof = on;
     }
 }
 
@@ -37,7 +128,14 @@ impl Write for Stream {
 impl Read for Stream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self.inner.read(buf) {
-            Err(ref err) if has_reached_end_of_sdtout(err) => Ok(0),
+            Err(ref err) if self.eio_as_eof && has_reached_end_of_sdtout(err) => Ok(0),
+            result => result,
+        }
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        match self.inner.read_vectored(bufs) {
+            Err(ref err) if self.eio_as_eof && has_reached_end_of_sdtout(err) => Ok(0),
             result => result,
         }
     }
@@ -49,6 +147,114 @@ impl AsRawFd for Stream {
     }
 }
 
+impl AsFd for Stream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+impl From<Stream> for OwnedFd {
+    fn from(stream: Stream) -> Self {
+        stream.inner.into()
+    }
+}
+
+/// An asynchronous [Stream] for the tokio runtime.
+///
+/// It's available with the `tokio` feature.
+///
+/// A descriptor is registered in a runtime reactor and switched to non-blocking mode,
+/// which is shared with all its duplicates.
+/// `EIO` is treated as EOF the same way as [Stream] does by default.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct TokioStream {
+    inner: tokio::io::unix::AsyncFd<Stream>,
+}
+
+#[cfg(feature = "tokio")]
+impl TokioStream {
+    /// Creates an asynchronous stream from a [Stream].
+    ///
+    /// It must be called within a tokio runtime.
+    pub fn new(stream: Stream) -> io::Result<Self> {
+        let fd = stream.as_raw_fd();
+        let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+        fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+
+        let inner = tokio::io::unix::AsyncFd::new(stream)?;
+
+        Ok(Self { inner })
+    }
+
+    /// Returns a reference to an underlying [Stream].
+    pub fn get_ref(&self) -> &Stream {
+        self.inner.get_ref()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncRead for TokioStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = ready!(this.inner.poll_read_ready_mut(cx))?;
+
+            // readiness is cleared on WouldBlock, so we get back to waiting
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|inner| inner.get_mut().read(unfilled)) {
+                Ok(Ok(n)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(err)) if err.kind() == io::ErrorKind::Interrupted => {}
+                Ok(Err(err)) => return Poll::Ready(Err(err)),
+                Err(_would_block) => {}
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio::io::AsyncWrite for TokioStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = ready!(this.inner.poll_write_ready_mut(cx))?;
+
+            match guard.try_io(|inner| inner.get_mut().write(buf)) {
+                Ok(Err(err)) if err.kind() == io::ErrorKind::Interrupted => {}
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => {}
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // writes are not buffered
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsRawFd for TokioStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
 /// PTY may doesn't have anything to read but the process is not DEAD,
 /// and this erorr may be returned.
 fn has_reached_end_of_sdtout(err: &std::io::Error) -> bool {
@@ -0,0 +1,390 @@
+use ptyprocess::{
+    Error, PtyProcessBuilder, Resource, Signal, SpawnError, Target, TtyProfile, WaitStatus,
+};
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::unix::io::FromRawFd,
+    process::Command,
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn rlimit() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "ulimit -n"]);
+    let proc = PtyProcessBuilder::new()
+        .rlimit(Resource::RLIMIT_NOFILE, 64, 64)
+        .spawn(cmd)
+        .unwrap();
+    let mut w = proc.get_pty_stream().unwrap();
+
+    let mut buf = String::new();
+    w.read_to_string(&mut buf).unwrap();
+    assert_eq!(buf, "64\r\n");
+
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn drop_signal() {
+    let file = std::env::temp_dir().join(format!("ptyprocess-drop-signal-{}", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(format!(
+        "trap 'echo usr1 > {}; exit 0' USR1; while true; do sleep 0.1; done",
+        file.display()
+    ));
+    let proc = PtyProcessBuilder::new()
+        .drop_signal(Signal::SIGUSR1, Duration::from_secs(2))
+        .spawn(cmd)
+        .unwrap();
+
+    // give the shell a time to set a trap
+    thread::sleep(Duration::from_millis(300));
+
+    drop(proc);
+
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "usr1\n");
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn skip_grantpt() {
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let proc = PtyProcessBuilder::new()
+        .skip_grantpt(true)
+        .spawn(cmd)
+        .unwrap();
+    let mut w = proc.get_pty_stream().unwrap();
+
+    let mut buf = String::new();
+    w.read_to_string(&mut buf).unwrap();
+    assert_eq!(buf, "Hello World\r\n");
+
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn stdout_null() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "echo out; echo err >&2"]);
+    let proc = PtyProcessBuilder::new()
+        .stdout(Target::Null)
+        .spawn(cmd)
+        .unwrap();
+    let mut w = proc.get_pty_stream().unwrap();
+
+    let mut buf = String::new();
+    w.read_to_string(&mut buf).unwrap();
+    assert_eq!(buf, "err\r\n");
+
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn stdout_fd() {
+    let (r, w) = nix::unistd::pipe().unwrap();
+
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let proc = PtyProcessBuilder::new()
+        .stdout(Target::Fd(w))
+        .spawn(cmd)
+        .unwrap();
+    nix::unistd::close(w).unwrap();
+
+    let mut buf = String::new();
+    unsafe { File::from_raw_fd(r) }
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "Hello World\n");
+
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn separate_stderr_pty() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "echo out; echo err >&2; test -t 2"]);
+    let proc = PtyProcessBuilder::new()
+        .separate_stderr_pty(true)
+        .spawn(cmd)
+        .unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "out\r\n");
+
+    let mut buf = String::new();
+    proc.stderr_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "err\r\n");
+
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn current_dir() {
+    let dir = std::env::temp_dir().canonicalize().unwrap();
+
+    let mut cmd = Command::new("pwd");
+    cmd.current_dir("/");
+    let proc = PtyProcessBuilder::new()
+        .current_dir(&dir)
+        .spawn(cmd)
+        .unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, format!("{}\r\n", dir.display()));
+}
+
+#[test]
+fn env_clear_except() {
+    let mut cmd = Command::new("env");
+    cmd.env("PTYPROCESS_TEST_VAR", "1");
+    let proc = PtyProcessBuilder::new()
+        .env_clear_except(&["PATH"])
+        .spawn(cmd)
+        .unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+
+    let mut vars = buf
+        .lines()
+        .map(|line| line.split('=').next().unwrap())
+        .collect::<Vec<_>>();
+    vars.sort_unstable();
+    assert_eq!(vars, ["PATH", "PTYPROCESS_TEST_VAR"]);
+}
+
+#[test]
+fn spawn_error_exec() {
+    let err = PtyProcessBuilder::new()
+        .try_spawn(Command::new("ptyprocess-not-existing-command"))
+        .unwrap_err();
+    assert_eq!(err, SpawnError::Exec(Error::ENOENT));
+}
+
+#[test]
+fn spawn_error_current_dir() {
+    let err = PtyProcessBuilder::new()
+        .current_dir("/ptyprocess/not/existing/dir")
+        .try_spawn(Command::new("pwd"))
+        .unwrap_err();
+    assert_eq!(err, SpawnError::CurrentDir(Error::ENOENT));
+}
+
+#[test]
+fn spawn_error_resource_limits() {
+    let err = PtyProcessBuilder::new()
+        .rlimit(Resource::RLIMIT_NOFILE, 64, 32)
+        .try_spawn(Command::new("true"))
+        .unwrap_err();
+    assert_eq!(err, SpawnError::ResourceLimits(Error::EINVAL));
+}
+
+#[test]
+fn spawn_error_redirect_streams() {
+    let err = PtyProcessBuilder::new()
+        .stdin(Target::Fd(-1))
+        .try_spawn(Command::new("true"))
+        .unwrap_err();
+    assert_eq!(err, SpawnError::RedirectStreams(Error::EBADF));
+}
+
+#[test]
+fn spawn_error_priority() {
+    // a privileged user is allowed to raise a priority, so the stage can't fail
+    if nix::unistd::geteuid().is_root() {
+        eprintln!("spawn_error_priority is skipped as it's run by root");
+        return;
+    }
+
+    let err = PtyProcessBuilder::new()
+        .nice(-1, true)
+        .try_spawn(Command::new("true"))
+        .unwrap_err();
+    assert_eq!(err, SpawnError::Priority(Error::EACCES));
+}
+
+// The rest of the stages can't be made to fail from a test:
+//
+// - SpawnError::ControllingTty: a forked child is never a process group leader so `setsid` succeeds,
+//   and a slave is already unlocked, so it can be opened unless the whole test process
+//   runs out of descriptors.
+// - SpawnError::TerminalSettings: a window size and termios flags are validated by the types
+//   and a fresh pty accepts any of them.
+// - SpawnError::CloseDescriptors: `close_range` and `close` are only called on descriptors
+//   the child owns, and their errors are ignored while closing inherited ones.
+// - SpawnError::OpenPty and SpawnError::Fork fail only when the system is out of resources.
+
+#[test]
+fn apply_settings_in_child() {
+    for in_child in [false, true] {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "stty size; stty -a | grep -o -- '-echo '"]);
+        let proc = PtyProcessBuilder::new()
+            .window_size(100, 30)
+            .apply_settings_in_child(in_child)
+            .spawn(cmd)
+            .unwrap();
+
+        let mut buf = String::new();
+        proc.get_pty_stream()
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert_eq!(buf, "30 100\r\n-echo \r\n");
+        assert!(!proc.get_echo().unwrap());
+    }
+}
+
+#[test]
+fn echo_and_raw() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "stty -a | grep -o -- ' -\\?icanon \\| -\\?echo '"]);
+    let proc = PtyProcessBuilder::new()
+        .raw(true)
+        .echo(true)
+        .terminate_delay(Duration::from_millis(10))
+        .spawn(cmd)
+        .unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, " -icanon \n echo \n");
+    assert!(proc.get_echo().unwrap());
+}
+
+#[test]
+fn profile() {
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let proc = PtyProcessBuilder::new()
+        .profile(TtyProfile::ScriptFriendly)
+        .spawn(cmd)
+        .unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "Hello World\n");
+
+    let proc = PtyProcessBuilder::new()
+        .profile(TtyProfile::Interactive)
+        .spawn(Command::new("cat"))
+        .unwrap();
+    assert!(proc.is_interactive().unwrap());
+}
+
+#[test]
+fn answer_device_attributes() {
+    let mut cmd = Command::new("head");
+    cmd.args(["-c", "7"]);
+    let mut proc = PtyProcessBuilder::new().raw(true).spawn(cmd).unwrap();
+
+    proc.answer_device_attributes().unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "\x1b[?1;2c");
+
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn nice() {
+    let proc = PtyProcessBuilder::new()
+        .nice(5, true)
+        .spawn(Command::new("nice"))
+        .unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "5\r\n");
+}
+
+#[test]
+fn keep_slave() {
+    let proc = PtyProcessBuilder::new().spawn(Command::new("cat")).unwrap();
+    assert_eq!(proc.slave_fd(), None);
+
+    let proc = PtyProcessBuilder::new()
+        .keep_slave(true)
+        .spawn(Command::new("cat"))
+        .unwrap();
+    let slave = proc.slave_fd().unwrap();
+
+    nix::unistd::write(slave, b"Hello World\n").unwrap();
+
+    let mut buf = [0; 64];
+    let n = proc.get_raw_handle().unwrap().read(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"Hello World\r\n");
+}
+
+#[test]
+fn nonblocking_master() {
+    let mut proc = PtyProcessBuilder::new()
+        .nonblocking_master(true)
+        .spawn(Command::new("cat"))
+        .unwrap();
+
+    let mut stream = proc.get_pty_stream().unwrap();
+    let mut buf = [0; 64];
+    let err = stream.read(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+
+    stream.write_all(b"Hello World\n").unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    let n = stream.read(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"Hello World\r\n");
+
+    proc.send_line("Hello").unwrap();
+    let output = proc
+        .read_until_prompt(b"Hello\r\n", Duration::from_secs(5))
+        .unwrap();
+    assert!(output.is_empty());
+
+    let err = proc.read_all(None).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+}
+
+#[cfg(feature = "spawn-timings")]
+#[test]
+fn spawn_timed() {
+    use ptyprocess::PtyProcess;
+
+    let (proc, timings) = PtyProcess::spawn_timed(Command::new("cat")).unwrap();
+    assert!(proc.is_alive().unwrap());
+    assert!(timings.open_pty > Duration::ZERO);
+    assert!(timings.exec > Duration::ZERO);
+}
@@ -1,5 +1,8 @@
-use ptyprocess::PtyProcess;
-use std::{io, process::Command};
+use ptyprocess::{Error, PtyProcess};
+use std::{
+    io::{self, Read},
+    process::Command,
+};
 
 #[test]
 fn empty() {
@@ -9,3 +12,27 @@ fn empty() {
         io::Error::from_raw_os_error(err as i32).kind()
     );
 }
+
+#[test]
+fn current_dir() {
+    let dir = std::env::temp_dir().canonicalize().unwrap();
+
+    let mut cmd = Command::new("pwd");
+    cmd.current_dir(&dir);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, format!("{}\r\n", dir.display()));
+}
+
+#[test]
+fn current_dir_not_found() {
+    let mut cmd = Command::new("pwd");
+    cmd.current_dir("/ptyprocess/not/existing/dir");
+    let err = PtyProcess::spawn(cmd).unwrap_err();
+    assert_eq!(err, Error::ENOENT);
+}
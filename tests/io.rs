@@ -1,7 +1,12 @@
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sys::termios::{tcgetattr, tcsetattr, SetArg, SpecialCharacterIndices};
+use nix::unistd::Pid;
 use ptyprocess::{PtyProcess, Signal, WaitStatus};
 use std::{
     io::{BufRead, BufReader, LineWriter, Read, Write},
-    process::Command,
+    ops::ControlFlow,
+    os::unix::io::{AsFd, AsRawFd, FromRawFd, OwnedFd},
+    process::{Command, Stdio},
     thread,
     time::Duration,
 };
@@ -259,3 +264,968 @@ fn read_to_end_after_process_is_gone() {
         assert_eq!(buf, b"")
     }
 }
+
+#[test]
+fn last_status_raw() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "exit 3"]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    assert_eq!(proc.last_status_raw(), None);
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 3));
+    assert_eq!(proc.last_status_raw(), Some(3 << 8));
+}
+
+#[test]
+fn suppress_kill_on_drop() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let pid = proc.pid();
+
+    let guard = proc.suppress_kill_on_drop();
+    drop(proc);
+
+    thread::sleep(Duration::from_millis(300));
+    assert!(nix::sys::signal::kill(pid, None).is_ok());
+
+    drop(guard);
+
+    nix::sys::signal::kill(pid, Signal::SIGKILL).unwrap();
+    nix::sys::wait::waitpid(pid, None).unwrap();
+}
+
+#[test]
+fn mark_exited() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let pid = proc.pid();
+
+    nix::sys::signal::kill(pid, Signal::SIGKILL).unwrap();
+    let status = nix::sys::wait::waitpid(pid, None).unwrap();
+    assert_eq!(status, WaitStatus::Signaled(pid, Signal::SIGKILL, false));
+
+    proc.mark_exited(status);
+
+    assert_eq!(proc.status().unwrap(), status);
+    assert_eq!(proc.wait().unwrap(), status);
+    assert!(!proc.is_alive().unwrap());
+}
+
+#[test]
+fn pipe_to() {
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut buf = Vec::new();
+    let n = proc.pipe_to(&mut buf).unwrap();
+
+    assert_eq!(buf, b"Hello World\r\n");
+    assert_eq!(n, buf.len() as u64);
+
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn emit_reset_sequences() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+
+    let mut buf = Vec::new();
+    proc.emit_reset_sequences(&mut buf).unwrap();
+
+    assert!(buf.starts_with(b"\x1b[?1000l"));
+    assert!(buf.windows(8).any(|w| w == b"\x1b[?2004l"));
+    assert!(buf.windows(8).any(|w| w == b"\x1b[?1049l"));
+}
+
+#[test]
+fn send_now() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    proc.send_now(b"hello cat\n").unwrap();
+
+    let mut reader = BufReader::new(proc.get_raw_handle().unwrap());
+    let mut buf = String::new();
+    reader.read_line(&mut buf).unwrap();
+    assert_eq!(buf, "hello cat\r\n");
+
+    assert!(proc.exit(true).unwrap());
+}
+
+#[test]
+fn from_pair() {
+    let pty = nix::pty::openpty(None, None).unwrap();
+    let master = unsafe { OwnedFd::from_raw_fd(pty.master) };
+    let slave = unsafe { OwnedFd::from_raw_fd(pty.slave) };
+
+    let stdout = slave.try_clone().unwrap();
+    // the child is reaped by PtyProcess
+    let mut child = Command::new("echo");
+    child
+        .arg("Hello World")
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(stdout))
+        .stderr(Stdio::null());
+    let pid = Pid::from_raw(child.spawn().unwrap().id() as i32);
+
+    let proc = PtyProcess::from_pair(master, slave, pid).unwrap();
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(pid, 0));
+
+    let mut buf = [0; 128];
+    let n = proc.get_raw_handle().unwrap().read(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"Hello World\r\n");
+}
+
+#[test]
+fn from_raw_master() {
+    let pty = nix::pty::openpty(None, None).unwrap();
+    let master = unsafe { OwnedFd::from_raw_fd(pty.master) };
+    let slave = unsafe { OwnedFd::from_raw_fd(pty.slave) };
+
+    let mut flags = tcgetattr(master.as_raw_fd()).unwrap();
+    flags.control_chars[SpecialCharacterIndices::VEOF as usize] = 0x01;
+    tcsetattr(master.as_raw_fd(), SetArg::TCSANOW, &flags).unwrap();
+
+    // the child is reaped by PtyProcess
+    let mut child = Command::new("echo");
+    child
+        .arg("Hello World")
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(slave))
+        .stderr(Stdio::null());
+    let pid = Pid::from_raw(child.spawn().unwrap().id() as i32);
+
+    let proc = PtyProcess::from_raw_master(master, pid).unwrap();
+    assert_eq!(proc.get_eof_char(), 0x01);
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(pid, 0));
+
+    let mut buf = [0; 128];
+    let n = proc.get_raw_handle().unwrap().read(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"Hello World\r\n");
+}
+
+#[test]
+fn from_raw_master_keeps_descriptor() {
+    let pty = nix::pty::openpty(None, None).unwrap();
+    let master = unsafe { OwnedFd::from_raw_fd(pty.master) };
+    drop(unsafe { OwnedFd::from_raw_fd(pty.slave) });
+
+    let pid = Pid::from_raw(Command::new("true").spawn().unwrap().id() as i32);
+
+    let proc = PtyProcess::from_raw_master(master, pid).unwrap();
+    assert_eq!(proc.as_fd().as_raw_fd(), pty.master);
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(pid, 0));
+}
+
+#[test]
+fn from_raw_master_not_a_pty() {
+    let (reader, writer) = nix::unistd::pipe().unwrap();
+    let reader = unsafe { OwnedFd::from_raw_fd(reader) };
+    drop(unsafe { OwnedFd::from_raw_fd(writer) });
+
+    assert!(PtyProcess::from_raw_master(reader, Pid::this()).is_err());
+}
+
+#[test]
+fn from_pair_of_different_ptys() {
+    let pty1 = nix::pty::openpty(None, None).unwrap();
+    let pty2 = nix::pty::openpty(None, None).unwrap();
+    let master = unsafe { OwnedFd::from_raw_fd(pty1.master) };
+    let slave = unsafe { OwnedFd::from_raw_fd(pty2.slave) };
+    unsafe {
+        drop(OwnedFd::from_raw_fd(pty1.slave));
+        drop(OwnedFd::from_raw_fd(pty2.master));
+    }
+
+    let err = PtyProcess::from_pair(master, slave, Pid::this()).unwrap_err();
+    assert_eq!(err, nix::Error::EINVAL);
+}
+
+#[test]
+fn wait_for_first_output() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "sleep 0.3; echo Hello World"]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    assert!(!proc
+        .wait_for_first_output(Duration::from_millis(50))
+        .unwrap());
+    assert!(proc.wait_for_first_output(Duration::from_secs(5)).unwrap());
+
+    let mut buf = String::new();
+    proc.get_raw_handle().unwrap().read_to_string(&mut buf).ok();
+    assert!(buf.starts_with("Hello World"));
+}
+
+#[test]
+fn exit_returns_as_soon_as_child_is_dead() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    proc.set_terminate_delay(Duration::from_secs(5));
+
+    let now = std::time::Instant::now();
+    assert!(proc.exit(true).unwrap());
+    assert!(now.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn finish() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "echo Hello World; exit 2"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let (output, status) = proc.finish().unwrap();
+    assert_eq!(output, b"Hello World\r\n");
+    assert_eq!(status, WaitStatus::Exited(proc.pid(), 2));
+}
+
+#[test]
+fn drain_startup() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "echo banner; read line; echo $line"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let n = proc.drain_startup(Duration::from_millis(300)).unwrap();
+    assert_eq!(n, b"banner\r\n".len());
+
+    proc.send_now(b"hello\n").unwrap();
+
+    let mut buf = String::new();
+    BufReader::new(proc.get_raw_handle().unwrap())
+        .read_line(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "hello\r\n");
+}
+
+#[test]
+fn poll() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "read line; echo $line"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let result = proc.poll(Duration::from_millis(100)).unwrap();
+    assert!(!result.readable);
+    assert!(result.writable);
+    assert!(!result.exited);
+
+    proc.send_now(b"hello\n").unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    let result = proc.poll(Duration::from_millis(100)).unwrap();
+    assert!(result.readable);
+    assert!(result.exited);
+}
+
+#[test]
+fn kill_after_child_is_reaped() {
+    let mut proc = PtyProcess::spawn(Command::new("true")).unwrap();
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+
+    assert_eq!(proc.kill(Signal::SIGTERM), Err(nix::Error::ESRCH));
+    assert!(!proc.is_alive().unwrap());
+}
+
+#[test]
+fn read_until_prompt() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "echo Hello World; printf '$ '; read line; echo $line"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let output = proc
+        .read_until_prompt(b"$ ", Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(output, b"Hello World\r\n");
+
+    let err = proc
+        .read_until_prompt(b"$ ", Duration::from_millis(300))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    proc.send_now(b"hello\n").unwrap();
+
+    let err = proc
+        .read_until_prompt(b"$ ", Duration::from_secs(5))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn read_until_prompt_keeps_the_rest() {
+    let mut cmd = Command::new("printf");
+    cmd.arg("a-pro-prompt> rest\\n");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let output = proc
+        .read_until_prompt(b"prompt> ", Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(output, b"a-pro-");
+
+    let mut rest = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut rest)
+        .unwrap();
+    assert_eq!(rest, "rest\r\n");
+}
+
+#[test]
+fn send_line_skip_echo() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "read line; echo got $line"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+    proc.set_echo(true, None).unwrap();
+
+    proc.send_line_skip_echo("hello", Duration::from_secs(5))
+        .unwrap();
+
+    let mut buf = String::new();
+    BufReader::new(proc.get_raw_handle().unwrap())
+        .read_line(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "got hello\r\n");
+}
+
+#[test]
+fn wait_detailed() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "exit 3"]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    let info = proc.wait_detailed().unwrap();
+    assert_eq!(info.code, Some(3));
+    assert_eq!(info.signal, None);
+    assert!(!info.core_dumped);
+    assert_eq!(info.raw, Some(3 << 8));
+
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    proc.kill(Signal::SIGKILL).unwrap();
+
+    let info = proc.wait_detailed().unwrap();
+    assert_eq!(info.code, None);
+    assert_eq!(info.signal, Some(Signal::SIGKILL));
+    assert!(!info.core_dumped);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn stream_eio_as_eof() {
+    let proc = PtyProcess::spawn(Command::new("true")).unwrap();
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+
+    let mut stream = proc.get_pty_stream().unwrap();
+    assert_eq!(stream.read(&mut [0; 128]).unwrap(), 0);
+
+    stream.set_eio_as_eof(false);
+    let err = stream.read(&mut [0; 128]).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(nix::libc::EIO));
+}
+
+#[test]
+fn drop_reaps_child_ignoring_signals() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "trap '' HUP INT TERM; sleep 10"]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+    let pid = proc.pid();
+
+    // give the shell a time to set a trap
+    thread::sleep(Duration::from_millis(300));
+
+    let now = std::time::Instant::now();
+    drop(proc);
+    assert!(now.elapsed() < Duration::from_secs(3));
+
+    assert_eq!(nix::sys::wait::waitpid(pid, None), Err(nix::Error::ECHILD));
+}
+
+#[test]
+fn drop_is_bounded_with_long_terminate_delay() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "trap '' HUP INT TERM; exec sleep 10"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+    proc.set_terminate_delay(Duration::from_secs(2));
+    let pid = proc.pid();
+
+    // give the shell a time to set a trap
+    thread::sleep(Duration::from_millis(300));
+
+    let now = std::time::Instant::now();
+    drop(proc);
+    assert!(now.elapsed() < Duration::from_millis(2500));
+
+    assert_eq!(nix::sys::wait::waitpid(pid, None), Err(nix::Error::ECHILD));
+}
+
+#[test]
+fn read_limited() {
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let output = proc.read_limited(5, Duration::from_secs(5)).unwrap();
+    assert_eq!(output, b"Hello");
+
+    let output = proc.read_limited(1024, Duration::from_secs(5)).unwrap();
+    assert_eq!(output, b" World\r\n");
+}
+
+#[test]
+fn read_limited_timeout() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+
+    let output = proc.read_limited(1024, Duration::from_millis(300)).unwrap();
+    assert!(output.is_empty());
+}
+
+#[test]
+fn writer() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let mut writer = proc.writer().unwrap();
+
+    thread::spawn(move || writer.write_all(b"hello cat\n").unwrap())
+        .join()
+        .unwrap();
+
+    let mut reader = BufReader::new(proc.get_raw_handle().unwrap());
+    let mut buf = String::new();
+    reader.read_line(&mut buf).unwrap();
+    assert_eq!(buf, "hello cat\r\n");
+}
+
+#[test]
+fn stream_read_all() {
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut stream = proc.get_pty_stream().unwrap();
+    stream.set_read_chunk_size(2);
+    assert_eq!(stream.read_all().unwrap(), b"Hello World\r\n");
+}
+
+#[test]
+fn sync_input() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let mut writer = proc.writer().unwrap();
+
+    writer.write_all(b"hello cat\n").unwrap();
+    proc.sync_input().unwrap();
+
+    let mut reader = BufReader::new(proc.get_raw_handle().unwrap());
+    let mut buf = String::new();
+    reader.read_line(&mut buf).unwrap();
+    assert_eq!(buf, "hello cat\r\n");
+}
+
+#[test]
+fn kill_by_name() {
+    for name in ["TERM", "SIGKILL", "int", "9"] {
+        let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+        proc.kill_by_name(name).unwrap();
+        assert!(matches!(
+            proc.wait().unwrap(),
+            WaitStatus::Signaled(_, _, false)
+        ));
+    }
+
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    assert_eq!(proc.kill_by_name("NOTASIGNAL"), Err(nix::Error::EINVAL));
+    assert_eq!(proc.kill_by_name("1000"), Err(nix::Error::EINVAL));
+}
+
+#[test]
+fn read_to_end_timeout() {
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut buf = Vec::new();
+    let n = proc
+        .read_to_end_timeout(&mut buf, Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(buf, b"Hello World\r\n");
+    assert_eq!(n, buf.len());
+
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "echo Hello World; sleep 5"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut buf = Vec::new();
+    let err = proc
+        .read_to_end_timeout(&mut buf, Duration::from_millis(500))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(buf, b"Hello World\r\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn descriptors_are_not_inherited() {
+    // std opens files with O_CLOEXEC so it's cleared to check that the descriptor is closed
+    let file = std::fs::File::open("/dev/null").unwrap();
+    fcntl(file.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::empty())).unwrap();
+
+    let mut cmd = Command::new("ls");
+    cmd.args(["-1", "/proc/self/fd"]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    // the 3rd descriptor is opened by ls itself to read the directory
+    assert_eq!(buf, "0\r\n1\r\n2\r\n3\r\n");
+
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn hangup() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    proc.hangup().unwrap();
+
+    assert_eq!(
+        proc.wait().unwrap(),
+        WaitStatus::Signaled(proc.pid(), Signal::SIGHUP, false)
+    );
+}
+
+#[test]
+fn handle_and_controller() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let pid = proc.pid();
+    let (mut file, mut controller) = proc.handle_and_controller().unwrap();
+
+    file.write_all(b"Hello World\n").unwrap();
+    let reader = thread::spawn(move || {
+        let mut line = String::new();
+        BufReader::new(file).read_line(&mut line).unwrap();
+        line
+    });
+    assert_eq!(reader.join().unwrap(), "Hello World\r\n");
+
+    assert_eq!(controller.status().unwrap(), WaitStatus::StillAlive);
+    controller.kill(Signal::SIGKILL).unwrap();
+    assert_eq!(
+        controller.wait().unwrap(),
+        WaitStatus::Signaled(pid, Signal::SIGKILL, false)
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn master_is_not_inherited() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let handle = proc.get_raw_handle().unwrap();
+    let fd = handle.as_raw_fd();
+
+    let output = Command::new("ls")
+        .arg("/proc/self/fd")
+        .stdout(Stdio::piped())
+        .output()
+        .unwrap();
+    let output = String::from_utf8(output.stdout).unwrap();
+
+    assert!(!output.lines().any(|line| line == fd.to_string()));
+}
+
+#[test]
+fn run_with_input() {
+    let mut cmd = Command::new("tr");
+    cmd.args(["a-z", "A-Z"]);
+    let (output, status) =
+        PtyProcess::run_with_input(cmd, b"hello\nworld", Duration::from_secs(5)).unwrap();
+
+    assert_eq!(output, b"HELLO\r\nWORLD");
+    assert!(matches!(status, WaitStatus::Exited(_, 0)));
+}
+
+#[test]
+fn run_with_input_child_exits_early() {
+    let mut cmd = Command::new("head");
+    cmd.args(["-n", "1"]);
+    let input = b"line\n".repeat(40_000);
+    let (output, status) = PtyProcess::run_with_input(cmd, &input, Duration::from_secs(5)).unwrap();
+
+    assert_eq!(output, b"line\r\n");
+    assert!(matches!(status, WaitStatus::Exited(_, 0)));
+}
+
+#[test]
+fn read_timeout() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let mut stream = proc.get_pty_stream().unwrap();
+
+    let mut buf = [0; 64];
+    let n = stream
+        .read_timeout(&mut buf, Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(n, None);
+
+    stream.write_all(b"Hello World\n").unwrap();
+
+    let n = proc
+        .read_timeout(&mut buf, Duration::from_secs(5))
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..n], b"Hello World\r\n");
+}
+
+#[test]
+fn slave_refcount_zero() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    assert!(!proc.slave_refcount_zero().unwrap());
+
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "exec 0<&- 1>&- 2>&-; sleep 5"]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    assert!(proc.slave_refcount_zero().unwrap());
+    assert!(proc.is_alive().unwrap());
+}
+
+#[test]
+fn wait_timeout() {
+    let mut cmd = Command::new("sleep");
+    cmd.arg("0.2");
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    assert_eq!(proc.wait_timeout(Duration::from_millis(10)).unwrap(), None);
+    assert_eq!(
+        proc.wait_timeout(Duration::from_secs(5)).unwrap(),
+        Some(WaitStatus::Exited(proc.pid(), 0))
+    );
+}
+
+#[test]
+fn wait_timeout_with_interval() {
+    let mut cmd = Command::new("sleep");
+    cmd.arg("0.1");
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    let now = std::time::Instant::now();
+    assert_eq!(
+        proc.wait_timeout_with_interval(Duration::from_secs(5), Duration::from_millis(500))
+            .unwrap(),
+        Some(WaitStatus::Exited(proc.pid(), 0))
+    );
+    // the exit is noticed only on a second check
+    assert!(now.elapsed() >= Duration::from_millis(500));
+}
+
+#[test]
+fn for_each_line() {
+    let mut cmd = Command::new("printf");
+    cmd.arg("first\nsecond\nthird");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut lines = Vec::new();
+    let status = proc
+        .for_each_line(Duration::from_secs(5), |line| {
+            lines.push(line.to_owned());
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+
+    assert_eq!(lines, ["first", "second", "third"]);
+    assert_eq!(status, WaitStatus::Exited(proc.pid(), 0));
+
+    let mut cmd = Command::new("yes");
+    cmd.arg("line");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut count = 0;
+    let status = proc
+        .for_each_line(Duration::from_secs(5), |line| {
+            assert_eq!(line, "line");
+            count += 1;
+            match count {
+                3 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        })
+        .unwrap();
+
+    assert_eq!(count, 3);
+    assert!(matches!(status, WaitStatus::Signaled(..)));
+}
+
+#[test]
+fn split() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let (reader, mut writer) = proc.split().unwrap();
+
+    let handle = thread::spawn(move || {
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).unwrap();
+        line
+    });
+
+    thread::spawn(move || writer.write_all(b"Hello World\n").unwrap())
+        .join()
+        .unwrap();
+
+    assert_eq!(handle.join().unwrap(), "Hello World\r\n");
+}
+
+#[test]
+fn stream_try_clone() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let mut writer = proc.get_pty_stream().unwrap();
+    let reader = writer.try_clone().unwrap();
+    assert_ne!(reader.as_raw_fd(), writer.as_raw_fd());
+
+    writer.write_all(b"Hello World\n").unwrap();
+
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).unwrap();
+    assert_eq!(line, "Hello World\r\n");
+}
+
+#[test]
+fn io_safety_conversions() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    assert!(nix::unistd::isatty(proc.as_fd().as_raw_fd()).unwrap());
+
+    let stream = proc.get_pty_stream().unwrap();
+    let fd = stream.as_fd().as_raw_fd();
+    let owned = OwnedFd::from(stream);
+    assert_eq!(owned.as_raw_fd(), fd);
+
+    let mut file = std::fs::File::from(owned);
+    file.write_all(b"Hello World\n").unwrap();
+
+    let mut line = String::new();
+    BufReader::new(file).read_line(&mut line).unwrap();
+    assert_eq!(line, "Hello World\r\n");
+}
+
+#[test]
+fn send_line_with() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let mut reader = BufReader::new(proc.get_pty_stream().unwrap());
+
+    proc.send_line("Hello").unwrap();
+    // ICRNL translates `\r` into `\n` on input
+    proc.send_line_with("World", b"\r").unwrap();
+
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "Hello\r\n");
+
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "World\r\n");
+}
+
+#[test]
+fn flush_input() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+
+    proc.send_now(b"Hello").unwrap();
+    thread::sleep(Duration::from_millis(100));
+    proc.flush_input().unwrap();
+    proc.send_now(b"World\n").unwrap();
+
+    let mut line = String::new();
+    BufReader::new(proc.get_pty_stream().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    assert_eq!(line, "World\r\n");
+}
+
+#[test]
+fn flush_output() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+
+    proc.send_line("Hello World").unwrap();
+    thread::sleep(Duration::from_millis(300));
+    proc.flush_output().unwrap();
+
+    let mut buf = [0; 64];
+    let n = proc
+        .read_timeout(&mut buf, Duration::from_millis(300))
+        .unwrap();
+    assert_eq!(n, None);
+}
+
+#[test]
+fn send_break() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    proc.send_break(None).unwrap();
+    proc.send_break(Some(100)).unwrap();
+    assert!(proc.is_alive().unwrap());
+}
+
+#[test]
+fn drain_remaining() {
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(proc.drain_remaining().unwrap(), b"Hello World\r\n");
+    assert_eq!(proc.drain_remaining().unwrap(), b"");
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn open_independent_handle() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    let mut handle = proc.open_independent_handle().unwrap();
+
+    let flags = nix::fcntl::fcntl(handle.as_raw_fd(), nix::fcntl::FcntlArg::F_GETFL).unwrap();
+    let flags = nix::fcntl::OFlag::from_bits_truncate(flags) | nix::fcntl::OFlag::O_NONBLOCK;
+    nix::fcntl::fcntl(handle.as_raw_fd(), nix::fcntl::FcntlArg::F_SETFL(flags)).unwrap();
+
+    let master = proc.get_raw_handle().unwrap();
+    let flags = nix::fcntl::fcntl(master.as_raw_fd(), nix::fcntl::FcntlArg::F_GETFL).unwrap();
+    assert!(!nix::fcntl::OFlag::from_bits_truncate(flags).contains(nix::fcntl::OFlag::O_NONBLOCK));
+
+    handle.write_all(b"Hello World\n").unwrap();
+
+    let mut line = String::new();
+    BufReader::new(master).read_line(&mut line).unwrap();
+    assert_eq!(line, "Hello World\r\n");
+}
+
+#[test]
+fn raw_pid() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    assert_eq!(proc.raw_pid(), proc.pid().as_raw());
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
+#[test]
+fn packet_mode() {
+    use ptyprocess::PacketFlags;
+
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    proc.set_packet_mode(true).unwrap();
+
+    proc.send_line("Hello World").unwrap();
+
+    let mut buf = [0; 64];
+    let (flags, n) = proc.read_packet(&mut buf).unwrap();
+    assert_eq!(flags, PacketFlags::default());
+    assert_eq!(&buf[..n], b"Hello World\r\n");
+
+    proc.flush_input().unwrap();
+
+    let (flags, n) = proc.read_packet(&mut buf).unwrap();
+    assert!(flags.flush_read);
+    assert_eq!(n, 0);
+}
+
+#[test]
+fn pipe() {
+    let mut cmd = Command::new("tr");
+    cmd.args(["a-z", "A-Z"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut output = Vec::new();
+    let n = proc.pipe(&b"hello\nworld"[..], &mut output).unwrap();
+
+    assert_eq!(output, b"HELLO\r\nWORLD");
+    assert_eq!(n, output.len() as u64);
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn pipe_child_exits_early() {
+    let mut cmd = Command::new("head");
+    cmd.args(["-n", "1"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    let input = b"line\n".repeat(40_000);
+    let mut output = Vec::new();
+    proc.pipe(&input[..], &mut output).unwrap();
+
+    assert_eq!(output, b"line\r\n");
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn status_helpers() {
+    let pid = Pid::from_raw(1);
+
+    let status = WaitStatus::Exited(pid, 3);
+    assert_eq!(ptyprocess::status_exit_code(&status), Some(3));
+    assert_eq!(ptyprocess::status_signal(&status), None);
+    assert!(!ptyprocess::status_core_dumped(&status));
+
+    let status = WaitStatus::Signaled(pid, Signal::SIGSEGV, true);
+    assert_eq!(ptyprocess::status_exit_code(&status), None);
+    assert_eq!(ptyprocess::status_signal(&status), Some(Signal::SIGSEGV));
+    assert!(ptyprocess::status_core_dumped(&status));
+
+    let status = WaitStatus::Stopped(pid, Signal::SIGSTOP);
+    assert_eq!(ptyprocess::status_signal(&status), None);
+}
+
+// On macOS and FreeBSD an output is lost once a child is reaped.
+#[cfg(target_os = "linux")]
+#[test]
+fn is_hung_up() {
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+
+    assert!(!proc.is_hung_up().unwrap());
+    assert_eq!(proc.drain_remaining().unwrap(), b"Hello World\r\n");
+    assert!(proc.is_hung_up().unwrap());
+}
+
+#[test]
+fn read_all() {
+    let mut cmd = Command::new("echo");
+    cmd.arg("Hello World");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+    assert_eq!(proc.read_all(None).unwrap(), b"Hello World\r\n");
+
+    let mut cmd = Command::new("yes");
+    cmd.arg("line");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+    let output = proc.read_all(Some(100_000)).unwrap();
+    assert_eq!(output.len(), 100_000);
+    assert!(output.starts_with(b"line\r\nline\r\n"));
+}
+
+#[test]
+fn set_cloexec() {
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+    let is_cloexec = |fd| {
+        FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD).unwrap())
+            .contains(FdFlag::FD_CLOEXEC)
+    };
+
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    assert!(is_cloexec(proc.as_fd().as_raw_fd()));
+
+    proc.set_cloexec(false).unwrap();
+    assert!(!is_cloexec(proc.as_fd().as_raw_fd()));
+
+    let mut stream = proc.get_pty_stream().unwrap();
+    assert!(is_cloexec(stream.as_raw_fd()));
+
+    stream.set_cloexec(false).unwrap();
+    assert!(!is_cloexec(stream.as_raw_fd()));
+}
+
+#[test]
+fn send_and_skip_echo() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "read a; read b; echo got $a $b"]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+    proc.set_echo(true, None).unwrap();
+
+    proc.send_and_skip_echo("hello\nworld\n", Duration::from_secs(5))
+        .unwrap();
+
+    let mut buf = String::new();
+    BufReader::new(proc.get_raw_handle().unwrap())
+        .read_line(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "got hello world\r\n");
+}
@@ -1,5 +1,11 @@
-use ptyprocess::PtyProcess;
-use std::{process::Command, time::Duration};
+use nix::pty::Winsize;
+use ptyprocess::{LocalFlags, OutputFlags, PtyProcess, ReadMode, WaitStatus};
+use std::{
+    io::{BufRead, BufReader, Read},
+    process::Command,
+    thread,
+    time::Duration,
+};
 
 #[test]
 fn default_win_size() {
@@ -15,6 +21,29 @@ fn set_win_size() {
     proc.set_window_size(100, 200).unwrap();
 
     assert_eq!(proc.get_window_size().unwrap(), (100, 200));
+    assert!(proc.assert_window_size(100, 200).unwrap());
+    assert!(!proc.assert_window_size(200, 100).unwrap());
+}
+
+#[test]
+fn win_size_order() {
+    let mut cmd = Command::new("stty");
+    cmd.arg("size");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    assert!(proc.assert_window_size(80, 24).unwrap());
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+
+    // stty prints rows first
+    assert_eq!(buf, "24 80\r\n");
+
+    proc.set_window_size(100, 30).unwrap();
+    assert_eq!(proc.get_window_size().unwrap(), (100, 30));
 }
 
 #[test]
@@ -36,3 +65,233 @@ fn set_echo() {
     assert!(is_set);
     assert!(proc.get_echo().unwrap());
 }
+
+#[test]
+fn is_interactive() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+
+    assert!(!proc.is_interactive().unwrap());
+
+    proc.set_echo(true, Some(Duration::from_millis(500)))
+        .unwrap();
+
+    assert!(proc.is_interactive().unwrap());
+}
+
+#[test]
+fn poll_win_size() {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg("stty cols 120 rows 40; sleep 1");
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(proc.poll_window_size().unwrap(), Some((120, 40)));
+    assert_eq!(proc.poll_window_size().unwrap(), None);
+
+    proc.set_window_size(100, 200).unwrap();
+
+    assert_eq!(proc.poll_window_size().unwrap(), None);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn foreground_command() {
+    let mut cmd = Command::new("sleep");
+    cmd.arg("1");
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(proc.foreground_command().unwrap(), "sleep");
+}
+
+#[test]
+fn ready_for_secret() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+
+    assert!(proc.echo_is_off().unwrap());
+    assert!(proc.ready_for_secret().unwrap());
+
+    proc.set_echo(true, Some(Duration::from_millis(500)))
+        .unwrap();
+
+    assert!(!proc.echo_is_off().unwrap());
+    assert!(!proc.ready_for_secret().unwrap());
+}
+
+#[test]
+fn writes_are_echoed() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+
+    assert!(!proc.writes_are_echoed().unwrap());
+
+    proc.set_echo(true, Some(Duration::from_millis(500)))
+        .unwrap();
+
+    assert!(proc.writes_are_echoed().unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn child_controlling_tty() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "tty; sleep 1"]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    let mut reader = BufReader::new(proc.get_raw_handle().unwrap());
+    let mut tty = String::new();
+    reader.read_line(&mut tty).unwrap();
+
+    assert_eq!(
+        proc.child_controlling_tty().unwrap().as_deref(),
+        Some(tty.trim_end())
+    );
+}
+
+#[test]
+fn with_echo() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+
+    let is_set = proc.with_echo(true, |proc| proc.get_echo()).unwrap();
+    assert!(is_set.unwrap());
+
+    assert!(!proc.get_echo().unwrap());
+}
+
+#[test]
+fn flags_snapshot() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+
+    let mut flags = proc.flags_snapshot().unwrap();
+    assert!(!flags.local_flags.contains(LocalFlags::ECHO));
+    assert!(flags.output_flags.contains(OutputFlags::ONLCR));
+
+    flags.local_flags |= LocalFlags::ECHO;
+    flags.output_flags.remove(OutputFlags::ONLCR);
+    proc.apply(&flags).unwrap();
+
+    assert!(proc.get_echo().unwrap());
+    assert_eq!(proc.flags_snapshot().unwrap(), flags);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn resource_usage() {
+    let mut cmd = Command::new("sh");
+    cmd.args([
+        "-c",
+        "i=0; while [ $i -lt 100000 ]; do i=$((i+1)); done; sleep 1",
+    ]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    thread::sleep(Duration::from_millis(500));
+
+    let usage = proc.resource_usage().unwrap();
+    assert!(usage.rss > 0);
+    assert!(usage.user_time + usage.system_time > Duration::ZERO);
+}
+
+#[test]
+fn spawn_matching_terminal() {
+    let size = Winsize {
+        ws_row: 40,
+        ws_col: 120,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let pty = nix::pty::openpty(Some(&size), None).unwrap();
+
+    let mut cmd = Command::new("stty");
+    cmd.arg("size");
+    let proc = PtyProcess::spawn_matching_terminal(cmd, pty.slave).unwrap();
+
+    let mut buf = String::new();
+    proc.get_pty_stream()
+        .unwrap()
+        .read_to_string(&mut buf)
+        .unwrap();
+    assert_eq!(buf, "40 120\r\n");
+
+    nix::unistd::close(pty.master).unwrap();
+    nix::unistd::close(pty.slave).unwrap();
+}
+
+#[test]
+fn spawn_matching_not_a_terminal() {
+    let proc = PtyProcess::spawn_matching_terminal(Command::new("cat"), -1).unwrap();
+    assert_eq!(proc.get_window_size().unwrap(), (80, 24));
+}
+
+#[test]
+fn is_session_leader() {
+    let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    assert!(proc.is_session_leader().unwrap());
+}
+
+#[test]
+fn read_mode() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    assert_eq!(proc.read_mode().unwrap(), ReadMode::Canonical);
+
+    let mut flags = proc.flags_snapshot().unwrap();
+    flags.local_flags.remove(LocalFlags::ICANON);
+    proc.apply(&flags).unwrap();
+
+    assert!(matches!(
+        proc.read_mode().unwrap(),
+        ReadMode::NonCanonical { .. }
+    ));
+}
+
+#[test]
+fn set_raw() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    assert!(!proc.is_raw().unwrap());
+
+    proc.set_raw().unwrap();
+    assert!(proc.is_raw().unwrap());
+
+    assert_eq!(
+        proc.read_mode().unwrap(),
+        ReadMode::NonCanonical { min: 1, time: 0 }
+    );
+
+    let flags = proc.flags_snapshot().unwrap();
+    assert!(!flags.local_flags.contains(LocalFlags::ISIG));
+    assert!(!flags.output_flags.contains(OutputFlags::OPOST));
+}
+
+#[test]
+fn set_eof_char() {
+    let mut proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+    proc.set_eof_char(0x01).unwrap();
+    proc.set_intr_char(0x02).unwrap();
+
+    assert_eq!(proc.get_eof_char(), 0x01);
+    assert_eq!(proc.get_intr_char(), 0x02);
+
+    proc.send_now(&[0x01]).unwrap();
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+}
+
+#[test]
+fn resize() {
+    let mut cmd = Command::new("sh");
+    cmd.args([
+        "-c",
+        "trap 'stty size; exit' WINCH; echo ready; while :; do sleep 0.1; done",
+    ]);
+    let mut proc = PtyProcess::spawn(cmd).unwrap();
+    let mut reader = BufReader::new(proc.get_pty_stream().unwrap());
+
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "ready\r\n");
+
+    proc.resize(100, 50).unwrap();
+
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "50 100\r\n");
+}
@@ -0,0 +1,130 @@
+// The tests are run with `cargo test --features tokio`.
+#![cfg(feature = "tokio")]
+
+use ptyprocess::{stream::TokioStream, PtyProcess, WaitStatus};
+use std::{
+    future::{poll_fn, Future},
+    io,
+    pin::Pin,
+    process::Command,
+    task::{ready, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+#[test]
+fn tokio_stream() {
+    block_on(async {
+        let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+        let mut stream = proc.get_tokio_stream().unwrap();
+
+        write_all(&mut stream, b"Hello World\n").await.unwrap();
+
+        let mut output = Vec::new();
+        while output.len() < 13 {
+            let mut buf = [0; 64];
+            let n = read(&mut stream, &mut buf).await.unwrap();
+            output.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(output, b"Hello World\r\n");
+    });
+}
+
+#[test]
+fn tokio_stream_read_until_eof() {
+    block_on(async {
+        let mut cmd = Command::new("echo");
+        cmd.arg("Hello World");
+        let proc = PtyProcess::spawn(cmd).unwrap();
+        let mut stream = proc.get_tokio_stream().unwrap();
+
+        let mut output = Vec::new();
+        loop {
+            let mut buf = [0; 64];
+            match read(&mut stream, &mut buf).await.unwrap() {
+                0 => break,
+                n => output.extend_from_slice(&buf[..n]),
+            }
+        }
+
+        assert_eq!(output, b"Hello World\r\n");
+        assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 0));
+    });
+}
+
+#[test]
+fn split_async() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    block_on(async {
+        let proc = PtyProcess::spawn(Command::new("cat")).unwrap();
+        let (mut reader, mut writer) = proc.split_async().unwrap();
+        assert_send(&reader);
+        assert_send(&writer);
+
+        poll_fn(|cx| Pin::new(&mut writer).poll_write(cx, b"Hello World\n"))
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        while output.len() < 13 {
+            let mut buf = [0; 64];
+            let mut buf = ReadBuf::new(&mut buf);
+            poll_fn(|cx| Pin::new(&mut reader).poll_read(cx, &mut buf))
+                .await
+                .unwrap();
+            output.extend_from_slice(buf.filled());
+        }
+        assert_eq!(output, b"Hello World\r\n");
+    });
+}
+
+#[test]
+fn exited() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "sleep 0.1; exit 3"]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    let status = block_on(async { proc.exited().unwrap().await.unwrap() });
+    assert_eq!(status, WaitStatus::Exited(proc.pid(), 3));
+}
+
+#[test]
+fn exited_dropped_before_exit() {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "sleep 0.1; exit 3"]);
+    let proc = PtyProcess::spawn(cmd).unwrap();
+
+    block_on(async {
+        let mut exited = proc.exited().unwrap();
+        let pending = poll_fn(|cx| Poll::Ready(Pin::new(&mut exited).poll(cx).is_pending())).await;
+        assert!(pending);
+    });
+
+    assert_eq!(proc.wait().unwrap(), WaitStatus::Exited(proc.pid(), 3));
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+async fn read(stream: &mut TokioStream, buf: &mut [u8]) -> io::Result<usize> {
+    poll_fn(|cx| {
+        let mut buf = ReadBuf::new(buf);
+        ready!(Pin::new(&mut *stream).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    })
+    .await
+}
+
+async fn write_all(stream: &mut TokioStream, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = poll_fn(|cx| Pin::new(&mut *stream).poll_write(cx, buf)).await?;
+        buf = &buf[n..];
+    }
+
+    Ok(())
+}